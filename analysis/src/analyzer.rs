@@ -0,0 +1,224 @@
+// An analyzer is a tokenizer followed by a chain of token filters. Which analyzer runs depends on the field (the html tag the
+// text is in) and the page language, both set in the analysis section of the config, so the crawler and the indexer
+// always turn the same text into the same terms
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::AnalysisConfig;
+use crate::filter::{self, TokenFilter, Stopwords, Stemmers};
+use crate::normalize;
+use crate::tokenizer::{self, Tokenizer};
+
+// the indexedwords and crawledwords word columns are varchar(64)
+pub const MAX_TERM_LENGTH: usize = 64;
+// and their surface columns varchar(512)
+pub const MAX_SURFACE_LENGTH: usize = 512;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    // what gets indexed, changed by every filter
+    pub term: String,
+    // the text as it was on the page, kept for highlighting
+    pub surface: String,
+    pub position: u32
+}
+
+impl Token {
+    pub fn new(text: &str, position: u32) -> Self {
+        return Token {
+            term: text.to_string(),
+            surface: text.to_string(),
+            position: position
+        }
+    }
+}
+
+pub struct Analyzer {
+    tokenizer: Box<dyn Tokenizer + Send + Sync>,
+    filters: Vec<Box<dyn TokenFilter + Send + Sync>>,
+    fallback_language: String
+}
+
+impl Analyzer {
+    // position carries on from the last call, so several runs of text from one page get positions that dont overlap
+    pub fn analyze(&self, text: &str, language: &str, position: &mut u32) -> Vec<Token> {
+        let language = filter::resolve_language(language, &self.fallback_language);
+        let mut tokens = vec![];
+
+        'tokens: for token in self.tokenizer.tokenize(text, position) {
+            let mut token = token;
+            for filter in &self.filters {
+                token = match filter.filter(token, language) {
+                    Some(t) => t,
+                    None => continue 'tokens
+                };
+            }
+            tokens.push(token);
+        }
+
+        return tokens;
+    }
+}
+
+#[derive(Clone)]
+pub struct Analyzers {
+    analyzers: HashMap<String, Arc<Analyzer>>,
+    fields: HashMap<String, String>,
+    languages: HashMap<String, HashMap<String, String>>,
+    fallback_language: String,
+    remove_diacritics: bool
+}
+
+impl Analyzers {
+    pub fn new(conf: &AnalysisConfig) -> Result<Self, String> {
+        let stopwords = Stopwords::load(&conf.stopwords_dir)?;
+        return Analyzers::with_stopwords(conf, stopwords);
+    }
+
+    pub fn with_stopwords(conf: &AnalysisConfig, stopwords: Stopwords) -> Result<Self, String> {
+        let stopwords = Arc::new(stopwords);
+        let stemmers = Arc::new(Stemmers::new());
+
+        let mut analyzers = HashMap::new();
+        for (name, analyzer_conf) in &conf.analyzers {
+            let mut filters: Vec<Box<dyn TokenFilter + Send + Sync>> = vec![];
+            for filter_name in &analyzer_conf.filters {
+                filters.push(match filter_name.as_str() {
+                    "normalize" => Box::new(filter::NormalizeFilter{}),
+                    "alphanumeric" => Box::new(filter::AlphanumericFilter{}),
+                    "remove_diacritics" => Box::new(filter::RemoveDiacriticsFilter{}),
+                    "max_length" => Box::new(filter::MaxLengthFilter{ max_length: MAX_TERM_LENGTH }),
+                    "stopwords" => Box::new(filter::StopwordFilter{ stopwords: stopwords.clone() }),
+                    "stem" => Box::new(filter::StemFilter{ stemmers: stemmers.clone() }),
+                    _ => return Err(format!("Unknown filter '{}' in analyzer '{}'", filter_name, name))
+                });
+            }
+
+            analyzers.insert(name.clone(), Arc::new(Analyzer {
+                tokenizer: tokenizer::from_name(&analyzer_conf.tokenizer)?,
+                filters: filters,
+                fallback_language: conf.fallback_language.clone()
+            }));
+        }
+
+        let default = match conf.fields.get("default") {
+            Some(t) => t,
+            None => return Err("analysis.fields needs a default analyzer".to_string())
+        };
+        for name in conf.fields.values().chain(conf.languages.values().flat_map(|t| t.values())) {
+            if !analyzers.contains_key(name) {
+                return Err(format!("Unknown analyzer '{}'", name));
+            }
+        }
+
+        return Ok(Analyzers {
+            remove_diacritics: conf.analyzers[default].filters.iter().any(|t| t == "remove_diacritics"),
+            analyzers: analyzers,
+            fields: conf.fields.clone(),
+            languages: conf.languages.clone(),
+            fallback_language: conf.fallback_language.clone()
+        });
+    }
+
+    // the language overrides win over the field mapping, and "default" fills in for any field that isnt listed
+    pub fn get(&self, field: &str, language: &str) -> &Analyzer {
+        let fields = [field, "default"];
+
+        let language = filter::resolve_language(language, &self.fallback_language);
+        let name = self.languages.get(&filter::primary_subtag(language))
+            .and_then(|overrides| fields.iter().find_map(|t| overrides.get(*t)))
+            .or_else(|| fields.iter().find_map(|t| self.fields.get(*t)))
+            .expect("analysis.fields has no default analyzer");
+
+        return &self.analyzers[name];
+    }
+
+    // the backend only drops punctuation, lowercases a query and strips accents when the default analyzer does, it has no stemmer.
+    // This is the same folding, so surface forms can be stored under the key the backend will look them up with
+    pub fn fold_query(&self, text: &str) -> String {
+        let normalized: String = normalize::normalize(text).chars().filter(|c| c.is_alphanumeric()).collect();
+        if !self.remove_diacritics {
+            return normalized;
+        }
+        return normalize::remove_diacritics(&normalized);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AnalyzerConfig;
+
+    fn analyzers() -> Analyzers {
+        let conf = AnalysisConfig {
+            stopwords_dir: String::new(),
+            fallback_language: "en".to_string(),
+            analyzers: HashMap::from([
+                ("standard".to_string(), AnalyzerConfig {
+                    tokenizer: "whitespace".to_string(),
+                    filters: ["normalize", "alphanumeric", "stopwords", "stem", "remove_diacritics", "max_length"].iter().map(|t| t.to_string()).collect()
+                }),
+                ("exact".to_string(), AnalyzerConfig {
+                    tokenizer: "word".to_string(),
+                    filters: vec!["normalize".to_string()]
+                }),
+            ]),
+            fields: HashMap::from([("default".to_string(), "standard".to_string()), ("code".to_string(), "exact".to_string())]),
+            languages: HashMap::from([("ja".to_string(), HashMap::from([("default".to_string(), "exact".to_string())]))])
+        };
+
+        let mut stopwords = Stopwords::empty();
+        stopwords.add("en", "the\nof");
+        return Analyzers::with_stopwords(&conf, stopwords).unwrap();
+    }
+
+    #[test]
+    fn analyzer_chain() {
+        let analyzers = analyzers();
+        let mut position = 0;
+
+        let tokens = analyzers.get("p", "en-US").analyze("The Running of the Élan's", "en-US", &mut position);
+        let terms: Vec<(&str, &str, u32)> = tokens.iter().map(|t| (t.term.as_str(), t.surface.as_str(), t.position)).collect();
+        assert_eq!(terms, vec![("run", "Running", 1), ("elan", "Élan's", 4)]);
+        assert_eq!(position, 5);
+
+        // a field with its own analyzer, and a language override
+        let tokens = analyzers.get("code", "en").analyze("e-Mail", "en", &mut position);
+        assert_eq!(tokens.iter().map(|t| t.term.as_str()).collect::<Vec<_>>(), vec!["e", "mail"]);
+        assert_eq!(tokens[0].position, 5);
+        let tokens = analyzers.get("p", "ja").analyze("Running", "ja", &mut position);
+        assert_eq!(tokens[0].term, "running");
+
+        // a page in no language we could detect gets english stopwords and stemming
+        let tokens = analyzers.get("p", "und").analyze("The Running", "und", &mut position);
+        assert_eq!(tokens.iter().map(|t| t.term.as_str()).collect::<Vec<_>>(), vec!["run"]);
+        let tokens = analyzers.get("p", "").analyze("The Running", "", &mut position);
+        assert_eq!(tokens.iter().map(|t| t.term.as_str()).collect::<Vec<_>>(), vec!["run"]);
+
+        assert_eq!(analyzers.fold_query("Élan's"), "elans");
+    }
+
+    // the backend runs the same cases through its Fold
+    #[test]
+    fn fold_query_fixture() {
+        let folding = analyzers();
+        let conf = AnalysisConfig {
+            stopwords_dir: String::new(),
+            fallback_language: "en".to_string(),
+            analyzers: HashMap::from([("exact".to_string(), AnalyzerConfig {
+                tokenizer: "word".to_string(),
+                filters: vec!["normalize".to_string()]
+            })]),
+            fields: HashMap::from([("default".to_string(), "exact".to_string())]),
+            languages: HashMap::new()
+        };
+        let keeping = Analyzers::with_stopwords(&conf, Stopwords::empty()).unwrap();
+
+        for line in include_str!("../testdata/fold.tsv").lines().filter(|t| !t.is_empty() && !t.starts_with('#')) {
+            let columns: Vec<&str> = line.split('\t').collect();
+            assert_eq!(folding.fold_query(columns[0]), columns[1], "{}", columns[0]);
+            assert_eq!(keeping.fold_query(columns[0]), columns[2], "{}", columns[0]);
+        }
+    }
+}
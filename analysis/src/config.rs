@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AnalysisConfig {
+    // directory of <language>.txt stopword lists, used by the stopwords filter
+    pub stopwords_dir: String,
+    // pages whose language couldnt be detected ("und") get the stopwords, stemmer and overrides of this language
+    pub fallback_language: String,
+    // named analyzers, each a tokenizer and the filters its tokens go through in order
+    pub analyzers: HashMap<String, AnalyzerConfig>,
+    // which analyzer each field (html tag) uses. "default" covers every field that isnt listed
+    pub fields: HashMap<String, String>,
+    // per language overrides of fields, keyed by the primary subtag ("ja", not "ja-JP")
+    pub languages: HashMap<String, HashMap<String, String>>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AnalyzerConfig {
+    pub tokenizer: String,
+    pub filters: Vec<String>
+}
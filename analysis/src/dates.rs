@@ -0,0 +1,71 @@
+// Dates in utc without a date crate, for the few places that write or read one: WARC-Date headers in the crawler and the
+// --since and --until of indexer reindex. Both go through the day count of the proleptic gregorian calendar, with years
+// counted from march so the leap day comes last
+
+// yyyy-mm-dd to unix seconds at midnight utc
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    let parts: Vec<i64> = match date.split('-').map(|t| t.parse::<i64>()).collect() {
+        Ok(t) => t,
+        Err(_) => return Err(format!("'{}' is not a yyyy-mm-dd date", date))
+    };
+    let (year, month, day) = match parts.as_slice() {
+        [year, month, day] if (1..=12).contains(month) && (1..=31).contains(day) => (*year, *month, *day),
+        _ => return Err(format!("'{}' is not a yyyy-mm-dd date", date))
+    };
+    return Ok(days_from_civil(year, month, day) * 86400);
+}
+
+// unix seconds to yyyy-mm-ddThh:mm:ssZ
+pub fn format_timestamp(seconds: i64) -> String {
+    let (days, second_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    return format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, second_of_day / 3600, second_of_day % 3600 / 60, second_of_day % 60);
+}
+
+// days since 1970-01-01
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+// (year, month, day) of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_roundtrip() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2000-03-01"), Ok(951868800));
+        assert_eq!(parse_date("2024-12-31"), Ok(1735603200));
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("yesterday").is_err());
+
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951868800 + 3723), "2000-03-01T01:02:03Z");
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+
+        // every day from before the epoch to past 2100, leap years and centuries included
+        for days in -1000..60000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
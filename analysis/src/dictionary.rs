@@ -0,0 +1,39 @@
+// What we know about a word on its own, before it is stemmed or weighed. The stopword lists are the only dictionary for
+// now, the word "the" is a stopword in english and a valid word in a language without a list
+
+use crate::filter::Stopwords;
+
+pub trait Dictionary {
+    fn get_word_status(&self, word: &str, language: &str) -> WordType;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WordType {
+    Valid,
+    StopWord,
+}
+
+impl Dictionary for Stopwords {
+    fn get_word_status(&self, word: &str, language: &str) -> WordType {
+        if self.contains(word, language) {
+            return WordType::StopWord;
+        }
+        return WordType::Valid;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopwords_per_language() {
+        let mut stopwords = Stopwords::empty();
+        stopwords.add("en", "# english\nthe\nOf");
+
+        assert_eq!(stopwords.get_word_status("the", "en-GB"), WordType::StopWord);
+        assert_eq!(stopwords.get_word_status("of", "en"), WordType::StopWord);
+        assert_eq!(stopwords.get_word_status("search", "en"), WordType::Valid);
+        assert_eq!(stopwords.get_word_status("the", "de"), WordType::Valid);
+    }
+}
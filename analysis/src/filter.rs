@@ -0,0 +1,202 @@
+// Token filters, each one takes a token and returns it changed or drops it. An analyzer runs them in the order they are configured
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use rust_stemmers::Algorithm;
+
+use crate::analyzer::Token;
+use crate::dictionary::{Dictionary, WordType};
+use crate::normalize;
+
+pub trait TokenFilter {
+    fn filter(&self, token: Token, language: &str) -> Option<Token>;
+}
+
+// NFKC and case folding
+pub struct NormalizeFilter {}
+
+impl TokenFilter for NormalizeFilter {
+    fn filter(&self, mut token: Token, _language: &str) -> Option<Token> {
+        token.term = normalize::normalize(&token.term);
+        return Some(token);
+    }
+}
+
+// strips everything but letters and digits from any script, and drops tokens with nothing left
+pub struct AlphanumericFilter {}
+
+impl TokenFilter for AlphanumericFilter {
+    fn filter(&self, mut token: Token, _language: &str) -> Option<Token> {
+        token.term = token.term.chars().filter(|c| c.is_alphanumeric()).collect();
+        if token.term.is_empty() {
+            return None;
+        }
+        return Some(token);
+    }
+}
+
+pub struct RemoveDiacriticsFilter {}
+
+impl TokenFilter for RemoveDiacriticsFilter {
+    fn filter(&self, mut token: Token, _language: &str) -> Option<Token> {
+        token.term = normalize::remove_diacritics(&token.term);
+        return Some(token);
+    }
+}
+
+// the database stores terms in varchar(64), longer ones are almost always junk like base64 anyway
+pub struct MaxLengthFilter {
+    pub max_length: usize
+}
+
+impl TokenFilter for MaxLengthFilter {
+    fn filter(&self, token: Token, _language: &str) -> Option<Token> {
+        if token.term.len() > self.max_length {
+            return None;
+        }
+        return Some(token);
+    }
+}
+
+// drops stopwords of the tokens language. A language without a list has no stopwords,
+// so a german page is never filtered with english rules
+pub struct StopwordFilter {
+    pub stopwords: Arc<Stopwords>
+}
+
+impl TokenFilter for StopwordFilter {
+    fn filter(&self, token: Token, language: &str) -> Option<Token> {
+        if self.stopwords.get_word_status(&token.term, language) == WordType::StopWord {
+            return None;
+        }
+        return Some(token);
+    }
+}
+
+// Snowball stemmers, so "running", "runs" and "run" land on one term. Languages snowball doesnt cover are left as written
+pub struct StemFilter {
+    pub stemmers: Arc<Stemmers>
+}
+
+impl TokenFilter for StemFilter {
+    fn filter(&self, mut token: Token, language: &str) -> Option<Token> {
+        match self.stemmers.get(language) {
+            Some(t) => token.term = t.stem(&token.term).into_owned(),
+            None => {}
+        };
+        return Some(token);
+    }
+}
+
+// stopwords are loaded from <stopwords_dir>/<language>.txt with one word per line and # for comments,
+// and normalized the same way tokens are before they reach the filter
+pub struct Stopwords {
+    lists: HashMap<String, HashSet<String>>
+}
+
+impl Stopwords {
+    pub fn empty() -> Self {
+        return Stopwords {
+            lists: HashMap::new()
+        }
+    }
+
+    pub fn load(stopwords_dir: &str) -> Result<Self, String> {
+        let mut stopwords = Stopwords::empty();
+
+        let entries = match std::fs::read_dir(stopwords_dir) {
+            Ok(t) => t,
+            Err(t) => return Err(format!("Couldnt read stopwords from {}: {}", stopwords_dir, t))
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|t| t.to_str()) != Some("txt") {
+                continue;
+            }
+
+            let language = match path.file_stem().and_then(|t| t.to_str()) {
+                Some(t) => t.to_lowercase(),
+                None => continue
+            };
+
+            match std::fs::read_to_string(&path) {
+                Ok(t) => stopwords.add(&language, &t),
+                Err(t) => return Err(format!("Couldnt read stopwords from {}: {}", path.display(), t))
+            }
+        }
+
+        return Ok(stopwords);
+    }
+
+    pub fn add(&mut self, language: &str, list: &str) {
+        let words = self.lists.entry(language.to_string()).or_default();
+
+        for line in list.lines() {
+            let word = line.trim();
+            if word.is_empty() || word.starts_with('#') {
+                continue;
+            }
+            words.insert(normalize::normalize(word));
+        }
+    }
+
+    pub fn contains(&self, word: &str, language: &str) -> bool {
+        return match self.lists.get(&primary_subtag(language)) {
+            Some(t) => t.contains(word),
+            None => false
+        };
+    }
+}
+
+pub struct Stemmers {
+    stemmers: HashMap<&'static str, rust_stemmers::Stemmer>
+}
+
+impl Stemmers {
+    pub fn new() -> Self {
+        let algorithms = [
+            ("ar", Algorithm::Arabic),
+            ("da", Algorithm::Danish),
+            ("nl", Algorithm::Dutch),
+            ("en", Algorithm::English),
+            ("fi", Algorithm::Finnish),
+            ("fr", Algorithm::French),
+            ("de", Algorithm::German),
+            ("el", Algorithm::Greek),
+            ("hu", Algorithm::Hungarian),
+            ("it", Algorithm::Italian),
+            ("nb", Algorithm::Norwegian),
+            ("no", Algorithm::Norwegian),
+            ("pt", Algorithm::Portuguese),
+            ("ro", Algorithm::Romanian),
+            ("ru", Algorithm::Russian),
+            ("es", Algorithm::Spanish),
+            ("sv", Algorithm::Swedish),
+            ("ta", Algorithm::Tamil),
+            ("tr", Algorithm::Turkish),
+        ];
+
+        return Stemmers {
+            stemmers: algorithms.into_iter().map(|(language, algorithm)| (language, rust_stemmers::Stemmer::create(algorithm))).collect()
+        };
+    }
+
+    pub fn get(&self, language: &str) -> Option<&rust_stemmers::Stemmer> {
+        return self.stemmers.get(primary_subtag(language).as_str());
+    }
+}
+
+// "und" is what the crawler stores when it couldnt tell the language, those pages are analyzed as the fallback language
+pub fn resolve_language<'a>(language: &'a str, fallback: &'a str) -> &'a str {
+    return match primary_subtag(language).as_str() {
+        "und" | "" => fallback,
+        _ => language
+    };
+}
+
+// "en-GB" uses the english list and stemmer
+pub fn primary_subtag(language: &str) -> String {
+    return language.split('-').next().unwrap_or("").to_lowercase();
+}
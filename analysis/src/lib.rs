@@ -0,0 +1,15 @@
+// Text analysis shared by the crawler and the indexer, so both sides (and the backend, which mirrors the folding in go)
+// agree on what a term looks like
+
+pub mod normalize;
+pub mod tokenizer;
+pub mod filter;
+pub mod dictionary;
+pub mod analyzer;
+pub mod config;
+pub mod positions;
+pub mod page_text;
+pub mod dates;
+
+pub use analyzer::{Analyzer, Analyzers, Token};
+pub use config::{AnalysisConfig, AnalyzerConfig};
@@ -0,0 +1,72 @@
+// Folds the different ways of writing a word onto one form. "Ångström", "ANGSTROM" and "angstrom" all end up as "angstrom".
+// This happens in two steps: normalize (NFKC + case folding) and remove_diacritics. They are separate filters because
+// stemmers know their languages accents, "häuser" stems to "haus" but "hauser" might not, so accents go after the stemmer
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+// NFKC turns compatibility characters into their plain forms (ligatures, fullwidth letters, the angstrom sign),
+// then case folding gets rid of case
+pub fn normalize(text: &str) -> String {
+    return case_fold(&text.nfkc().collect::<String>());
+}
+
+// lowercasing is most of case folding, these are the common characters where the two differ
+pub fn case_fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            'ſ' => folded.push('s'),
+            _ => folded.extend(c.to_lowercase())
+        }
+    }
+    return folded;
+}
+
+// splits characters into a base letter and combining marks and drops the marks. A few letters dont decompose,
+// so they are mapped by hand
+pub fn remove_diacritics(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ø' => stripped.push('o'),
+            'Ø' => stripped.push('O'),
+            'æ' => stripped.push_str("ae"),
+            'Æ' => stripped.push_str("AE"),
+            'œ' => stripped.push_str("oe"),
+            'Œ' => stripped.push_str("OE"),
+            'đ' | 'ð' => stripped.push('d'),
+            'Đ' | 'Ð' => stripped.push('D'),
+            'ł' => stripped.push('l'),
+            'Ł' => stripped.push('L'),
+            'þ' => stripped.push_str("th"),
+            'Þ' => stripped.push_str("TH"),
+            'ħ' => stripped.push('h'),
+            'ı' => stripped.push('i'),
+            _ => stripped.push(c)
+        }
+    }
+    return stripped.nfc().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folding() {
+        let fold = |t: &str| remove_diacritics(&normalize(t));
+
+        assert_eq!(fold("Ångström"), "angstrom");
+        // U+212B ANGSTROM SIGN
+        assert_eq!(fold("\u{212B}ngström"), "angstrom");
+        assert_eq!(fold("ANGSTROM"), "angstrom");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("ﬁnancial"), "financial");
+        assert_eq!(fold("Łódź"), "lodz");
+        assert_eq!(fold("Ｆｕｌｌ"), "full");
+        assert_eq!(normalize("Ångström"), "ångström");
+    }
+}
@@ -0,0 +1,203 @@
+// The text of a page the way the parser leaves it, before any analysis. The crawler keeps a compressed copy of it per page
+// so the indexer can run it through the analyzers again after the weights, stopwords or tokenizers change, without
+// fetching the web again. Both sides count terms with analyze here so a reindexed page gets the same words a crawl would.
+//
+// Stored as zlib over (parent, text) pairs, each string a varint length followed by its bytes
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::analyzer::{Analyzers, MAX_SURFACE_LENGTH, MAX_TERM_LENGTH};
+use crate::positions::{read_varint, write_varint};
+
+// positions skipped between two runs of text from different tags, more than any phrase or proximity window spans
+pub const FIELD_POSITION_GAP: u32 = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRun {
+    pub parent: String,
+    pub text: String,
+}
+
+// a term counted over the whole page, per tag it was found in
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageTerm {
+    pub term: String,
+    // the most common way the term was written on the page, for highlighting
+    pub surface: String,
+    pub parent: String,
+    pub count: i32,
+    // where in the page each occurrence is, counted in tokens
+    pub positions: Vec<u32>,
+}
+
+pub fn compress(runs: &[TextRun]) -> Vec<u8> {
+    let mut raw = vec![];
+    for run in runs {
+        write_string(&mut raw, &run.parent);
+        write_string(&mut raw, &run.text);
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    // writing into a vec cant fail
+    encoder.write_all(&raw).expect("Couldnt compress page text");
+    return encoder.finish().expect("Couldnt compress page text");
+}
+
+pub fn decompress(compressed: &[u8]) -> Result<Vec<TextRun>, String> {
+    let mut raw = vec![];
+    match ZlibDecoder::new(compressed).read_to_end(&mut raw) {
+        Ok(_) => {},
+        Err(t) => return Err(format!("Couldnt decompress page text {}", t))
+    };
+
+    let mut runs = vec![];
+    let mut rest = raw.as_slice();
+    while !rest.is_empty() {
+        let parent = match read_string(&mut rest) {
+            Some(t) => t,
+            None => return Err(String::from("Page text is truncated"))
+        };
+        let text = match read_string(&mut rest) {
+            Some(t) => t,
+            None => return Err(String::from("Page text is truncated"))
+        };
+        runs.push(TextRun { parent: parent, text: text });
+    }
+    return Ok(runs);
+}
+
+// runs the text through the analyzer configured for its tag and the page language, and counts the resulting terms per tag.
+// Positions carry on across text runs so they are positions in the whole page, with a gap wherever the tag changes so a
+// phrase cant match across the end of the title into the first paragraph
+pub fn analyze(runs: &[TextRun], language: &str, analyzers: &Analyzers) -> Vec<PageTerm> {
+    let mut terms: HashMap<(String, String), (i32, HashMap<String, i32>, Vec<u32>)> = HashMap::new();
+    let mut position: u32 = 0;
+    let mut previous_parent: Option<&str> = None;
+
+    for run in runs.iter() {
+        match previous_parent {
+            Some(t) if t != run.parent => position = position.saturating_add(FIELD_POSITION_GAP),
+            _ => {}
+        };
+        previous_parent = Some(&run.parent);
+
+        let analyzer = analyzers.get(&run.parent, language);
+
+        for token in analyzer.analyze(&run.text, language, &mut position) {
+            // max_length is up to the config, the columns are not
+            if token.term.len() > MAX_TERM_LENGTH || token.surface.len() > MAX_SURFACE_LENGTH {
+                continue;
+            }
+
+            let (count, surfaces, positions) = terms.entry((token.term, run.parent.clone())).or_default();
+            *count += 1;
+            *surfaces.entry(token.surface).or_default() += 1;
+            positions.push(token.position);
+        }
+    }
+
+    return terms.into_iter().map(|((term, parent), (count, surfaces, positions))| {
+        PageTerm {
+            term: term,
+            surface: most_common(surfaces),
+            parent: parent,
+            count: count,
+            positions: positions
+        }
+    }).collect();
+}
+
+// ties go to the smallest string so the same page always picks the same form
+fn most_common(surfaces: HashMap<String, i32>) -> String {
+    return surfaces.into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+        .map(|t| t.0)
+        .unwrap_or_default();
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) {
+    write_varint(out, string.len() as u64);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn read_string(rest: &mut &[u8]) -> Option<String> {
+    let (length, read) = read_varint(rest)?;
+    let end = read.checked_add(length as usize)?;
+    let bytes = rest.get(read..end)?;
+    let string = String::from_utf8(bytes.to_vec()).ok()?;
+    *rest = &rest[end..];
+    return Some(string);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AnalysisConfig, AnalyzerConfig};
+    use crate::filter::Stopwords;
+
+    #[test]
+    fn page_text_roundtrip() {
+        let runs = vec![
+            TextRun { parent: "title".to_string(), text: "Running fast".to_string() },
+            TextRun { parent: "p".to_string(), text: "".to_string() },
+            TextRun { parent: "p".to_string(), text: "ünïcödé ".repeat(200) },
+        ];
+        let compressed = compress(&runs);
+
+        assert!(compressed.len() < runs[2].text.len());
+        assert_eq!(decompress(&compressed), Ok(runs));
+        assert!(decompress(&compressed[..compressed.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn page_text_position_gap() {
+        let conf = AnalysisConfig {
+            stopwords_dir: String::new(),
+            fallback_language: "en".to_string(),
+            analyzers: HashMap::from([("standard".to_string(), AnalyzerConfig {
+                tokenizer: "whitespace".to_string(),
+                filters: vec!["normalize".to_string()]
+            })]),
+            fields: HashMap::from([("default".to_string(), "standard".to_string())]),
+            languages: HashMap::new()
+        };
+        let analyzers = Analyzers::with_stopwords(&conf, Stopwords::empty()).unwrap();
+        let runs = vec![
+            TextRun { parent: "title".to_string(), text: "new york".to_string() },
+            TextRun { parent: "p".to_string(), text: "city".to_string() },
+            TextRun { parent: "p".to_string(), text: "hall".to_string() },
+        ];
+
+        let mut terms = analyze(&runs, "en", &analyzers);
+        terms.sort_by(|a, b| a.positions.cmp(&b.positions));
+        let positions: Vec<(&str, &[u32])> = terms.iter().map(|t| (t.term.as_str(), t.positions.as_slice())).collect();
+        // "york city" is no phrase, but two runs of the same tag still follow each other
+        assert_eq!(positions, vec![("new", &[0][..]), ("york", &[1][..]), ("city", &[102][..]), ("hall", &[103][..])]);
+    }
+
+    #[test]
+    fn page_text_column_limits() {
+        // no max_length filter in the config, the column limits still hold
+        let conf = AnalysisConfig {
+            stopwords_dir: String::new(),
+            fallback_language: "en".to_string(),
+            analyzers: HashMap::from([("standard".to_string(), AnalyzerConfig {
+                tokenizer: "whitespace".to_string(),
+                filters: vec![]
+            })]),
+            fields: HashMap::from([("default".to_string(), "standard".to_string())]),
+            languages: HashMap::new()
+        };
+        let analyzers = Analyzers::with_stopwords(&conf, Stopwords::empty()).unwrap();
+        let runs = vec![TextRun { parent: "p".to_string(), text: format!("short {} end", "a".repeat(MAX_TERM_LENGTH + 1)) }];
+
+        let mut terms: Vec<String> = analyze(&runs, "en", &analyzers).into_iter().map(|t| t.term).collect();
+        terms.sort();
+        assert_eq!(terms, vec!["end".to_string(), "short".to_string()]);
+    }
+}
@@ -0,0 +1,75 @@
+// Position lists are stored as the gaps between sorted positions, each gap a LEB128 varint. Most gaps in a page fit in one byte.
+// This is the same varint go reads with encoding/binary.Uvarint, so the backend can decode them without anything extra
+
+pub fn encode(positions: &[u32]) -> Vec<u8> {
+    let mut sorted = positions.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut encoded = Vec::with_capacity(sorted.len());
+    let mut last = 0;
+    for position in sorted {
+        write_varint(&mut encoded, (position - last) as u64);
+        last = position;
+    }
+    return encoded;
+}
+
+// stops at a truncated varint instead of failing, whatever came before it is still good
+pub fn decode(encoded: &[u8]) -> Vec<u32> {
+    let mut positions = vec![];
+    let mut last: u32 = 0;
+    let mut rest = encoded;
+
+    loop {
+        match read_varint(rest) {
+            Some((gap, length)) => {
+                last += gap as u32;
+                positions.push(last);
+                rest = &rest[length..];
+            }
+            None => return positions
+        }
+    }
+}
+
+pub fn write_varint(out: &mut Vec<u8>, value: u64) {
+    let mut value = value;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// the value and how many bytes it took, None at the end of the input or on a truncated varint
+pub fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_roundtrip() {
+        let positions = vec![300, 0, 5, 6, 70000, 5];
+        let encoded = encode(&positions);
+
+        // 0, 5, 1, 294 (two bytes), 69700 (three bytes)
+        assert_eq!(encoded.len(), 8);
+        assert_eq!(decode(&encoded), vec![0, 5, 6, 300, 70000]);
+        assert_eq!(decode(&encoded[..encoded.len() - 1]), vec![0, 5, 6, 300]);
+    }
+}
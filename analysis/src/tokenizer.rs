@@ -0,0 +1,40 @@
+// Splits text into tokens. Every token gets the next position, filters that drop tokens later leave gaps,
+// so "the new york times" still has "new" right before "york"
+
+use crate::analyzer::Token;
+
+pub trait Tokenizer {
+    fn tokenize(&self, text: &str, position: &mut u32) -> Vec<Token>;
+}
+
+// splits on whitespace only, punctuation inside a token is left for the filters. "don't" stays one token
+pub struct WhitespaceTokenizer {}
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str, position: &mut u32) -> Vec<Token> {
+        return text.split_whitespace().map(|t| Token::new(t, next(position))).collect();
+    }
+}
+
+// splits on anything that isnt a letter or digit, "e-mail" is two tokens
+pub struct WordTokenizer {}
+
+impl Tokenizer for WordTokenizer {
+    fn tokenize(&self, text: &str, position: &mut u32) -> Vec<Token> {
+        return text.split(|c: char| !c.is_alphanumeric()).filter(|t| !t.is_empty()).map(|t| Token::new(t, next(position))).collect();
+    }
+}
+
+fn next(position: &mut u32) -> u32 {
+    let current = *position;
+    *position += 1;
+    return current;
+}
+
+pub fn from_name(name: &str) -> Result<Box<dyn Tokenizer + Send + Sync>, String> {
+    return match name {
+        "whitespace" => Ok(Box::new(WhitespaceTokenizer{})),
+        "word" => Ok(Box::new(WordTokenizer{})),
+        _ => Err(format!("Unknown tokenizer '{}'", name))
+    };
+}
@@ -9,6 +9,7 @@ import (
 type Config struct {
 	Backend  BackendConfig  `yaml:"backend"`
 	Database PostgresDBInfo `yaml:"database"`
+	Analysis AnalysisConfig `yaml:"analysis"`
 }
 
 type PostgresDBInfo struct {
@@ -19,7 +20,30 @@ type PostgresDBInfo struct {
 }
 
 type BackendConfig struct {
-	PageSize int `yaml:"page_size"`
+	PageSize int      `yaml:"page_size"`
+	// urls of the indexers serving their segments, like http://searchengine-indexer:3334
+	SegmentServers []string `yaml:"segment_servers"`
+}
+
+// only the parts of the analysis config the backend needs to fold queries like the default analyzer does
+type AnalysisConfig struct {
+	Analyzers map[string]AnalyzerConfig `yaml:"analyzers"`
+	Fields    map[string]string         `yaml:"fields"`
+}
+
+type AnalyzerConfig struct {
+	Tokenizer string   `yaml:"tokenizer"`
+	Filters   []string `yaml:"filters"`
+}
+
+// the backend has no stemmer, but accents have to come off queries when they came off the indexed terms
+func (a AnalysisConfig) RemovesDiacritics() bool {
+	for _, filter := range a.Analyzers[a.Fields["default"]].Filters {
+		if filter == "remove_diacritics" {
+			return true
+		}
+	}
+	return false
 }
 
 func ReadFromFile(filename string) (Config, error) {
@@ -3,9 +3,9 @@ package database
 import (
 	"database/sql"
 	"fmt"
-	"strings"
+	"sort"
 
-	_ "github.com/lib/pq"
+	"github.com/lib/pq"
 	"prushton.com/search/config"
 )
 
@@ -15,8 +15,10 @@ type SiteMetadata struct {
 }
 
 type Database struct {
-	Client   *sql.DB
-	PageSize int
+	Client         *sql.DB
+	PageSize       int
+	// indexers serving their segments, the words come from them instead of indexedwords when there are any
+	SegmentServers []string
 }
 
 func Connect(dbinfo config.PostgresDBInfo, PageSize int) (Database, error) {
@@ -33,7 +35,15 @@ func Connect(dbinfo config.PostgresDBInfo, PageSize int) (Database, error) {
 }
 
 func (self *Database) Get_words(word string, page int) (map[string]int64, error) {
-	rows, err := self.Client.Query("SELECT * FROM indexedwords WHERE word = $1 ORDER BY weight DESC LIMIT $2", word, self.PageSize*page)
+	if len(self.SegmentServers) > 0 {
+		return self.segmentWords(word, page)
+	}
+
+	// words are indexed by their stem, termforms maps every form the indexer has seen back to it
+	rows, err := self.Client.Query(`SELECT documents.url, indexedwords.word, indexedwords.weight FROM indexedwords
+		JOIN documents ON documents.id = indexedwords.doc_id
+		WHERE indexedwords.word IN (SELECT stem FROM termforms WHERE surface = $1 UNION SELECT $1)
+		ORDER BY indexedwords.weight DESC LIMIT $2`, word, self.PageSize*page)
 
 	if err != nil {
 		return nil, err
@@ -49,20 +59,66 @@ func (self *Database) Get_words(word string, page int) (map[string]int64, error)
 
 		rows.Scan(&url, &word, &weight)
 
-		wordmap[url] = int64(weight)
+		// a word can map to more than one stem, a page matching several of them gets the sum
+		wordmap[url] += int64(weight)
 	}
 
 	return wordmap, nil
 }
 
+// positions of a query word in each of the given pages. A word that maps to several stems gets their positions merged
+func (self *Database) Get_positions(word string, urls []string) (map[string][]uint32, error) {
+	var positions map[string][]uint32
+	var err error
+	if len(self.SegmentServers) > 0 {
+		positions, err = self.segmentPositions(word, urls)
+	} else {
+		positions, err = self.tablePositions(word, urls)
+	}
+
+	if err != nil {
+		return nil, err
+	}
+
+	for url := range positions {
+		sort.Slice(positions[url], func(i, j int) bool { return positions[url][i] < positions[url][j] })
+	}
+
+	return positions, nil
+}
+
+func (self *Database) tablePositions(word string, urls []string) (map[string][]uint32, error) {
+	rows, err := self.Client.Query(`SELECT documents.url, indexedwords.positions FROM indexedwords
+		JOIN documents ON documents.id = indexedwords.doc_id
+		WHERE indexedwords.word IN (SELECT stem FROM termforms WHERE surface = $1 UNION SELECT $1)
+		AND documents.url = ANY($2) AND indexedwords.positions IS NOT NULL`, word, pq.Array(urls))
+
+	if err != nil {
+		return nil, err
+	}
+
+	var positions map[string][]uint32 = make(map[string][]uint32)
+
+	for rows.Next() {
+		var url string
+		var encoded []byte
+
+		rows.Scan(&url, &encoded)
+
+		positions[url] = append(positions[url], DecodePositions(encoded)...)
+	}
+
+	return positions, nil
+}
+
 func (self *Database) Get_site_metadata(query_urls []string) (map[string]SiteMetadata, error) {
 	if len(query_urls) == 0 {
 		return make(map[string]SiteMetadata), nil
 	}
 
-	var query = fmt.Sprintf("SELECT * FROM sitemetadata WHERE url IN ('%s');", strings.Join(query_urls, "', '"))
-	// fmt.Printf("q: %s\n", query)
-	rows, err := self.Client.Query(query)
+	rows, err := self.Client.Query(`SELECT documents.url, sitemetadata.title, sitemetadata.description FROM sitemetadata
+		JOIN documents ON documents.id = sitemetadata.doc_id
+		WHERE documents.url = ANY($1)`, pq.Array(query_urls))
 
 	if err != nil {
 		return map[string]SiteMetadata{}, err
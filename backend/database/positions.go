@@ -0,0 +1,21 @@
+package database
+
+import "encoding/binary"
+
+// the indexer stores positions as varint encoded gaps between sorted positions
+func DecodePositions(encoded []byte) []uint32 {
+	positions := make([]uint32, 0)
+	var last uint32 = 0
+
+	for len(encoded) > 0 {
+		gap, n := binary.Uvarint(encoded)
+		if n <= 0 {
+			break
+		}
+		last += uint32(gap)
+		positions = append(positions, last)
+		encoded = encoded[n:]
+	}
+
+	return positions
+}
@@ -0,0 +1,112 @@
+package database
+
+import (
+	"bytes"
+	"encoding/json"
+	"fmt"
+	"net/http"
+	"sort"
+	"time"
+)
+
+// a posting as an indexer serves it from its segments
+type SegmentPosting struct {
+	Url       string   `json:"url"`
+	Term      string   `json:"term"`
+	Weight    int64    `json:"weight"`
+	Positions []uint32 `json:"positions"`
+}
+
+type postingsRequest struct {
+	Terms []string `json:"terms"`
+	Urls  []string `json:"urls,omitempty"`
+	Limit int      `json:"limit"`
+}
+
+var segmentClient = http.Client{Timeout: 10 * time.Second}
+
+// the stems a query word can stand for, the word itself included
+func (self *Database) stems(word string) ([]string, error) {
+	rows, err := self.Client.Query(`SELECT stem FROM termforms WHERE surface = $1 UNION SELECT $1`, word)
+	if err != nil {
+		return nil, err
+	}
+	defer rows.Close()
+
+	stems := make([]string, 0)
+	for rows.Next() {
+		var stem string
+		rows.Scan(&stem)
+		stems = append(stems, stem)
+	}
+	return stems, nil
+}
+
+// postings of the terms from every indexer, heaviest first. Each indexer only has the pages it indexed, so the lists are
+// merged before the limit applies. urls narrows it to those pages and limit 0 returns them all
+func SegmentPostings(servers []string, terms []string, urls []string, limit int) ([]SegmentPosting, error) {
+	body, err := json.Marshal(postingsRequest{Terms: terms, Urls: urls, Limit: limit})
+	if err != nil {
+		return nil, err
+	}
+
+	postings := make([]SegmentPosting, 0)
+	for _, server := range servers {
+		response, err := segmentClient.Post(server+"/postings", "application/json", bytes.NewReader(body))
+		if err != nil {
+			return nil, err
+		}
+
+		var served []SegmentPosting
+		if response.StatusCode == http.StatusOK {
+			err = json.NewDecoder(response.Body).Decode(&served)
+		} else {
+			err = fmt.Errorf("segment server %s answered %s", server, response.Status)
+		}
+		response.Body.Close()
+		if err != nil {
+			return nil, err
+		}
+		postings = append(postings, served...)
+	}
+
+	sort.SliceStable(postings, func(i, j int) bool { return postings[i].Weight > postings[j].Weight })
+	if limit > 0 && len(postings) > limit {
+		postings = postings[:limit]
+	}
+	return postings, nil
+}
+
+func (self *Database) segmentWords(word string, page int) (map[string]int64, error) {
+	stems, err := self.stems(word)
+	if err != nil {
+		return nil, err
+	}
+	postings, err := SegmentPostings(self.SegmentServers, stems, nil, self.PageSize*page)
+	if err != nil {
+		return nil, err
+	}
+
+	var wordmap map[string]int64 = make(map[string]int64)
+	for _, posting := range postings {
+		wordmap[posting.Url] += posting.Weight
+	}
+	return wordmap, nil
+}
+
+func (self *Database) segmentPositions(word string, urls []string) (map[string][]uint32, error) {
+	stems, err := self.stems(word)
+	if err != nil {
+		return nil, err
+	}
+	postings, err := SegmentPostings(self.SegmentServers, stems, urls, 0)
+	if err != nil {
+		return nil, err
+	}
+
+	var positions map[string][]uint32 = make(map[string][]uint32)
+	for _, posting := range postings {
+		positions[posting.Url] = append(positions[posting.Url], posting.Positions...)
+	}
+	return positions, nil
+}
@@ -0,0 +1,44 @@
+package database
+
+import (
+	"encoding/json"
+	"net/http"
+	"net/http/httptest"
+	"reflect"
+	"testing"
+)
+
+func segmentServer(t *testing.T, postings []SegmentPosting) *httptest.Server {
+	return httptest.NewServer(http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		var request postingsRequest
+		if r.URL.Path != "/postings" || json.NewDecoder(r.Body).Decode(&request) != nil {
+			t.Errorf("bad request to %s", r.URL.Path)
+		}
+		json.NewEncoder(w).Encode(postings)
+	}))
+}
+
+func TestSegmentPostingsMergesServers(t *testing.T) {
+	first := segmentServer(t, []SegmentPosting{{Url: "a", Term: "run", Weight: 3}, {Url: "b", Term: "run", Weight: 1}})
+	defer first.Close()
+	second := segmentServer(t, []SegmentPosting{{Url: "c", Term: "run", Weight: 2, Positions: []uint32{4}}})
+	defer second.Close()
+
+	postings, err := SegmentPostings([]string{first.URL, second.URL}, []string{"run"}, nil, 2)
+	if err != nil {
+		t.Fatal(err)
+	}
+	expected := []SegmentPosting{{Url: "a", Term: "run", Weight: 3}, {Url: "c", Term: "run", Weight: 2, Positions: []uint32{4}}}
+	if !reflect.DeepEqual(postings, expected) {
+		t.Errorf("got %v, expected %v", postings, expected)
+	}
+}
+
+func TestSegmentPostingsFailsOnBadServer(t *testing.T) {
+	server := httptest.NewServer(http.NotFoundHandler())
+	defer server.Close()
+
+	if _, err := SegmentPostings([]string{server.URL}, []string{"run"}, nil, 0); err == nil {
+		t.Error("expected an error from a server answering 404")
+	}
+}
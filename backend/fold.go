@@ -0,0 +1,70 @@
+package main
+
+import (
+	"strings"
+	"unicode"
+
+	"golang.org/x/text/unicode/norm"
+)
+
+// Mirrors Analyzers::fold_query in the analysis crate, so a query word matches the terms the crawler and indexer
+// wrote. analysis/testdata/fold.tsv has the cases both sides are tested against
+
+// characters where case folding differs from lowercasing
+var caseFolds = map[rune]string{
+	'ß': "ss",
+	'ẞ': "ss",
+	'ς': "σ",
+	'ſ': "s",
+}
+
+// letters that dont decompose, so dropping the marks leaves them as they are
+var undecomposed = map[rune]string{
+	'ø': "o", 'Ø': "o",
+	'æ': "ae", 'Æ': "ae",
+	'œ': "oe", 'Œ': "oe",
+	'đ': "d", 'Đ': "d", 'ð': "d", 'Ð': "d",
+	'ł': "l", 'Ł': "l",
+	'þ': "th", 'Þ': "th",
+	'ħ': "h", 'Ħ': "h",
+	'ı': "i",
+}
+
+func Fold(word string, removeDiacritics bool) string {
+	// NFKC turns ligatures, fullwidth letters and the angstrom sign into their plain forms, which can leave
+	// something that isnt a letter or digit behind ("½" is "1⁄2")
+	word = strings.Map(func(r rune) rune {
+		if unicode.IsLetter(r) || unicode.IsNumber(r) {
+			return r
+		}
+		return -1
+	}, norm.NFKC.String(word))
+
+	if removeDiacritics {
+		// the analysis crate drops every combining mark, not only the nonspacing ones
+		var stripped strings.Builder
+		for _, r := range norm.NFD.String(word) {
+			if unicode.Is(unicode.M, r) {
+				continue
+			}
+			if replacement, exists := undecomposed[r]; exists {
+				stripped.WriteString(replacement)
+				continue
+			}
+			stripped.WriteRune(r)
+		}
+		// put back together what NFD split that wasnt a mark, like hangul syllables
+		word = norm.NFC.String(stripped.String())
+	}
+
+	var folded strings.Builder
+	for _, r := range strings.ToLower(word) {
+		if replacement, exists := caseFolds[r]; exists {
+			folded.WriteString(replacement)
+			continue
+		}
+		folded.WriteRune(r)
+	}
+
+	return folded.String()
+}
@@ -0,0 +1,49 @@
+package main
+
+import (
+	"os"
+	"strings"
+	"testing"
+)
+
+func TestFold(t *testing.T) {
+	cases := map[string]string{
+		"Ångström": "angstrom",
+		"ANGSTROM": "angstrom",
+		"Straße":   "strasse",
+		"Łódź":     "lodz",
+		"café":     "cafe",
+	}
+
+	for input, expected := range cases {
+		if folded := Fold(input, true); folded != expected {
+			t.Errorf("Fold(%q) = %q, expected %q", input, folded, expected)
+		}
+	}
+
+	if folded := Fold("Ångström", false); folded != "ångström" {
+		t.Errorf("Fold without diacritic removal = %q, expected %q", folded, "ångström")
+	}
+}
+
+// the same cases the analysis crate tests Analyzers::fold_query with
+func TestFoldFixture(t *testing.T) {
+	file, err := os.ReadFile("../analysis/testdata/fold.tsv")
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	for _, line := range strings.Split(string(file), "\n") {
+		if line == "" || strings.HasPrefix(line, "#") {
+			continue
+		}
+		columns := strings.Split(line, "\t")
+
+		if folded := Fold(columns[0], true); folded != columns[1] {
+			t.Errorf("Fold(%q) = %q, expected %q", columns[0], folded, columns[1])
+		}
+		if folded := Fold(columns[0], false); folded != columns[2] {
+			t.Errorf("Fold(%q) without diacritic removal = %q, expected %q", columns[0], folded, columns[2])
+		}
+	}
+}
@@ -7,3 +7,5 @@ require github.com/lib/pq v1.10.9
 require github.com/joho/godotenv v1.5.1
 
 require gopkg.in/yaml.v3 v3.0.1
+
+require golang.org/x/text v0.14.0
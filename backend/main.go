@@ -77,14 +77,34 @@ func search(w http.ResponseWriter, r *http.Request) {
 
 	start := time.Now().UnixNano() / int64(time.Millisecond)
 
-	var nonAlphanumericRegex = regexp.MustCompile(`[^a-zA-Z0-9 ]+`)
+	// letters and digits from any script, the same characters the crawler keeps
+	var nonAlphanumericRegex = regexp.MustCompile(`[^\p{L}\p{N} ]+`)
+
+	// "quoted words" have to appear as an exact phrase
+	var phraseRegex = regexp.MustCompile(`"([^"]+)"`)
 
 	query := r.URL.Query()
 	rawSearch := query.Get("s")
+	removeDiacritics := conf.Analysis.RemovesDiacritics()
+
+	phrases := make([][]string, 0)
+	for _, match := range phraseRegex.FindAllStringSubmatch(rawSearch, -1) {
+		phrase := strings.Fields(nonAlphanumericRegex.ReplaceAllString(match[1], " "))
+		for i, word := range phrase {
+			phrase[i] = Fold(word, removeDiacritics)
+		}
+		if len(phrase) > 1 {
+			phrases = append(phrases, phrase)
+		}
+	}
+
 	rawSearch = nonAlphanumericRegex.ReplaceAllString(rawSearch, " ")
-	rawSearch = strings.ToLower(rawSearch)
 	search := strings.Split(rawSearch, " ")
 
+	for i, word := range search {
+		search[i] = Fold(word, removeDiacritics)
+	}
+
 	// fmt.Printf("Raw search: %s\n", rawSearch)
 	// fmt.Printf("Search: %s\n", search)
 
@@ -115,6 +135,8 @@ func search(w http.ResponseWriter, r *http.Request) {
 		Scores = addScoredURLs(Scores, newURLs)
 	}
 
+	Scores = scoreProximity(Scores, search, phrases)
+
 	// Sort the urls by score
 	SortedURLs := SortURLs(Scores)
 
@@ -174,6 +196,7 @@ func main() {
 		fmt.Println("Error connecting to database, exiting")
 		return
 	}
+	db.SegmentServers = conf.Backend.SegmentServers
 
 	http.HandleFunc("/search", search)
 
@@ -0,0 +1,165 @@
+package main
+
+import (
+	"fmt"
+	"sort"
+)
+
+// a word of a phrase and how far into the phrase it is. Stopwords arent indexed, so they leave a gap instead of a term
+type PhraseTerm struct {
+	Offset    uint32
+	Positions []uint32
+}
+
+func containsPosition(positions []uint32, position uint32) bool {
+	i := sort.Search(len(positions), func(i int) bool { return positions[i] >= position })
+	return i < len(positions) && positions[i] == position
+}
+
+// true if every term appears at its offset from some occurrence of the first one
+func PhraseMatch(terms []PhraseTerm) bool {
+	if len(terms) == 0 {
+		return true
+	}
+
+	for _, start := range terms[0].Positions {
+		if start < terms[0].Offset {
+			continue
+		}
+		origin := start - terms[0].Offset
+
+		matched := true
+		for _, term := range terms[1:] {
+			if !containsPosition(term.Positions, origin+term.Offset) {
+				matched = false
+				break
+			}
+		}
+		if matched {
+			return true
+		}
+	}
+	return false
+}
+
+// the length of the smallest window of the page that has every word in it, 0 if a word is missing
+func MinSpan(lists [][]uint32) uint32 {
+	type occurrence struct {
+		position uint32
+		list     int
+	}
+
+	occurrences := make([]occurrence, 0)
+	for i, list := range lists {
+		if len(list) == 0 {
+			return 0
+		}
+		for _, position := range list {
+			occurrences = append(occurrences, occurrence{position: position, list: i})
+		}
+	}
+	sort.Slice(occurrences, func(i, j int) bool { return occurrences[i].position < occurrences[j].position })
+
+	var best uint32 = 0
+	counts := make([]int, len(lists))
+	covered := 0
+	left := 0
+
+	for _, right := range occurrences {
+		if counts[right.list] == 0 {
+			covered++
+		}
+		counts[right.list]++
+
+		for covered == len(lists) {
+			span := right.position - occurrences[left].position + 1
+			if best == 0 || span < best {
+				best = span
+			}
+
+			counts[occurrences[left].list]--
+			if counts[occurrences[left].list] == 0 {
+				covered--
+			}
+			left++
+		}
+	}
+	return best
+}
+
+// quoted phrases have to appear exactly, pages without them are dropped. Pages with every word of the query get a bonus
+// the closer together the words are, up to double the score when they are right next to each other
+func scoreProximity(scores map[string]ScoredURL, words []string, phrases [][]string) map[string]ScoredURL {
+	queryWords := make([]string, 0)
+	seen := make(map[string]bool)
+	for _, word := range words {
+		if word != "" && !seen[word] {
+			seen[word] = true
+			queryWords = append(queryWords, word)
+		}
+	}
+
+	if (len(queryWords) < 2 && len(phrases) == 0) || len(scores) == 0 {
+		return scores
+	}
+
+	candidates := make([]string, 0, len(scores))
+	for url := range scores {
+		candidates = append(candidates, url)
+	}
+
+	// word -> url -> positions
+	positions := make(map[string]map[string][]uint32)
+	for _, word := range queryWords {
+		wordPositions, err := db.Get_positions(word, candidates)
+		if err != nil {
+			fmt.Printf("Error %s\n", err)
+			continue
+		}
+		positions[word] = wordPositions
+	}
+
+	for url, scored := range scores {
+		if !matchesPhrases(url, phrases, positions) {
+			delete(scores, url)
+			continue
+		}
+
+		// words that arent in any of the pages are stopwords or unknown, they dont count against the span
+		lists := make([][]uint32, 0)
+		for _, word := range queryWords {
+			if len(positions[word]) > 0 {
+				lists = append(lists, positions[word][url])
+			}
+		}
+		if len(lists) < 2 {
+			continue
+		}
+
+		span := MinSpan(lists)
+		if span == 0 {
+			continue
+		}
+		scored.Score += scored.Score * int64(len(lists)) / int64(span)
+		scores[url] = scored
+	}
+
+	return scores
+}
+
+func matchesPhrases(url string, phrases [][]string, positions map[string]map[string][]uint32) bool {
+	for _, phrase := range phrases {
+		terms := make([]PhraseTerm, 0)
+		for offset, word := range phrase {
+			if len(positions[word]) == 0 {
+				continue
+			}
+			terms = append(terms, PhraseTerm{Offset: uint32(offset), Positions: positions[word][url]})
+		}
+
+		if !PhraseMatch(terms) {
+			return false
+		}
+	}
+	return true
+}
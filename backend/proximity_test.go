@@ -0,0 +1,50 @@
+package main
+
+import (
+	"testing"
+
+	"prushton.com/search/database"
+)
+
+func TestDecodePositions(t *testing.T) {
+	// gaps 0, 5, 1, 294, 69700 as written by the indexer
+	encoded := []byte{0x00, 0x05, 0x01, 0xa6, 0x02, 0xc4, 0xa0, 0x04}
+	decoded := database.DecodePositions(encoded)
+	expected := []uint32{0, 5, 6, 300, 70000}
+
+	if len(decoded) != len(expected) {
+		t.Fatalf("decoded %v, expected %v", decoded, expected)
+	}
+	for i := range expected {
+		if decoded[i] != expected[i] {
+			t.Fatalf("decoded %v, expected %v", decoded, expected)
+		}
+	}
+}
+
+func TestPhraseMatch(t *testing.T) {
+	// "new york times" at 10, "new" and "times" elsewhere too
+	newPositions := []uint32{3, 10}
+	york := []uint32{11}
+	times := []uint32{1, 12}
+
+	if !PhraseMatch([]PhraseTerm{{0, newPositions}, {1, york}, {2, times}}) {
+		t.Errorf("expected new york times to match")
+	}
+	if PhraseMatch([]PhraseTerm{{0, york}, {1, newPositions}}) {
+		t.Errorf("expected york new not to match")
+	}
+	// "king of france" with "of" dropped as a stopword, france is still 2 after king
+	if !PhraseMatch([]PhraseTerm{{0, []uint32{4}}, {2, []uint32{6}}}) {
+		t.Errorf("expected a phrase with a stopword gap to match")
+	}
+}
+
+func TestMinSpan(t *testing.T) {
+	if span := MinSpan([][]uint32{{1, 20}, {5, 22}, {21}}); span != 3 {
+		t.Errorf("expected a span of 3, got %d", span)
+	}
+	if span := MinSpan([][]uint32{{1}, {}}); span != 0 {
+		t.Errorf("expected a missing word to give 0, got %d", span)
+	}
+}
@@ -0,0 +1,100 @@
+// Works out which encoding a page is in and decodes it to a String. The order is the one browsers use:
+// a byte order mark, then the charset in the content-type header, then a <meta> tag near the top of the page,
+// and if none of those say anything a statistical guess from the bytes themselves
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use regex::bytes::Regex;
+use std::sync::LazyLock;
+
+// browsers only look this far into the page for a <meta> charset
+const META_PRESCAN_LENGTH: usize = 1024;
+
+// covers both <meta charset="..."> and <meta http-equiv="Content-Type" content="text/html; charset=...">
+static META_CHARSET: LazyLock<Regex> = LazyLock::new(|| {
+    return Regex::new(r#"(?i)<meta[^>]*?charset\s*=\s*["']?\s*([a-z0-9_\-:.]+)"#).expect("meta charset regex did not compile");
+});
+
+pub fn decode(content: &[u8], content_type: Option<&str>, tld: Option<&str>) -> (String, &'static Encoding) {
+    let encoding = detect(content, content_type, tld);
+    // malformed bytes become U+FFFD instead of failing the whole page
+    let (text, _, _) = encoding.decode(content);
+    return (text.into_owned(), encoding);
+}
+
+pub fn detect(content: &[u8], content_type: Option<&str>, tld: Option<&str>) -> &'static Encoding {
+    match Encoding::for_bom(content) {
+        Some((t, _)) => return t,
+        None => {}
+    };
+
+    match content_type.and_then(from_content_type) {
+        Some(t) => return t,
+        None => {}
+    };
+
+    match from_meta(content) {
+        Some(t) => return t,
+        None => {}
+    };
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(content, true);
+    return detector.guess(tld.map(|t| t.as_bytes()), true);
+}
+
+fn from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    for parameter in content_type.split(';').skip(1) {
+        let (name, value) = match parameter.split_once('=') {
+            Some(t) => t,
+            None => continue
+        };
+
+        if name.trim().eq_ignore_ascii_case("charset") {
+            return Encoding::for_label(value.trim().trim_matches(|c| c == '"' || c == '\'').as_bytes());
+        }
+    }
+    return None;
+}
+
+fn from_meta(content: &[u8]) -> Option<&'static Encoding> {
+    let head = &content[..content.len().min(META_PRESCAN_LENGTH)];
+    let label = META_CHARSET.captures(head)?.get(1)?;
+
+    return match Encoding::for_label(label.as_bytes()) {
+        // a page that could be read far enough to find an ascii meta tag cant be utf-16, the spec says to treat it as utf-8
+        Some(t) if t == UTF_16BE || t == UTF_16LE => Some(UTF_8),
+        Some(t) if t.name() == "x-user-defined" => Some(WINDOWS_1252),
+        t => t
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, ISO_8859_2};
+
+    #[test]
+    fn charset_detection_order() {
+        let page = b"<html><head><meta charset=\"iso-8859-2\"></head><body>caf\xe9</body></html>";
+
+        assert_eq!(detect(page, Some("text/html; charset=Shift_JIS"), None), SHIFT_JIS);
+        assert_eq!(detect(page, Some("text/html"), None), ISO_8859_2);
+        assert_eq!(detect(b"\xef\xbb\xbfhello", Some("text/html; charset=Shift_JIS"), None), UTF_8);
+
+        let http_equiv = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\">";
+        assert_eq!(detect(http_equiv, None, None), WINDOWS_1252);
+        assert_eq!(detect(b"<meta charset=utf-16le>", None, None), UTF_8);
+    }
+
+    #[test]
+    fn charset_decode() {
+        let (text, encoding) = decode(b"<p>na\xefve caf\xe9</p>", Some("text/html; charset=\"windows-1252\""), None);
+        assert_eq!(encoding, WINDOWS_1252);
+        assert_eq!(text, "<p>naïve café</p>");
+
+        // no hints at all, the detector has to recognise the utf-8 on its own
+        let (text, _) = decode("<p>Ein schöner Tag für große Übungen</p>".as_bytes(), None, Some("de"));
+        assert_eq!(text, "<p>Ein schöner Tag für große Übungen</p>");
+    }
+}
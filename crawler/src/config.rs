@@ -1,10 +1,12 @@
 use serde::{Serialize, Deserialize};
 use log::LevelFilter;
+use analysis::AnalysisConfig;
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub crawler: CrawlerConfig,
-    pub database: PostgresDBInfo
+    pub database: PostgresDBInfo,
+    pub analysis: AnalysisConfig
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -21,7 +23,37 @@ pub struct CrawlerConfig {
     pub max_crawl_depth: i32,
     pub user_agent: String,
     pub seed_url: String,
-    pub log: String
+    pub log: String,
+    // in bytes, bodies are cut off at this size whether or not the server sends content-length
+    pub max_page_size: u64,
+    // parse the first max_page_size bytes of a page that is too big instead of skipping it
+    pub truncate_oversized_pages: bool,
+    // BCP-47 language tags to crawl, matched by prefix so "en" also allows "en-GB". An empty list crawls every language
+    pub languages: Vec<String>,
+    pub recrawl: RecrawlConfig,
+    pub warc: WarcConfig
+}
+
+// all values are in seconds
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecrawlConfig {
+    pub min_interval: i64,
+    pub max_interval: i64,
+    // interval given to a url the first time it is crawled
+    pub default_interval: i64,
+    // how often the scheduler looks for urls that are due to be crawled again
+    pub check_interval: u64,
+    // a page that was crawled before is tombstoned and taken out of the index after this many 404 or 410s in a row
+    pub gone_confirmations: i32
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WarcConfig {
+    // write every fetch to gzipped WARC files
+    pub enabled: bool,
+    pub dir: String,
+    // in bytes, a new file is started once the current one is this big
+    pub rotate_size: u64
 }
 
 impl Config {
@@ -1,12 +1,23 @@
 // Database trait should implement the following features:
+// Documents, mapping a 64 bit id (a hash of the url) to the url. Every other table keys on the id instead of the url string
 // Queue for urls storing url, depth, and crawler id. 0 refers to no assigned crawler
 // crawled words, storing the word, its parent element, the count, and the url
-// crawled data, storing the url, title, and a 512 character description
+// crawled data, storing the url, title, and a 512 character description. Every write notifies the indexer
+// crawled urls, storing when a url was last crawled, the hash of its content, and when it should be crawled again
+// crawled text, the compressed text of the last crawl of every page, kept after indexing so the indexer can reindex from it
 
 use std::time::SystemTime;
-use postgres::{Client, NoTls, error::SqlState};
+use postgres::{Client, GenericClient, NoTls, error::SqlState};
+use xxhash_rust::xxh3::xxh3_64;
 use crate::parser;
+use crate::scheduler::CrawlSchedule;
+use crate::http_request::CacheValidators;
 use crate::config::PostgresDBInfo;
+use log::{info, warn};
+use analysis::page_text;
+
+// the indexer listens on this channel, every committed page is sent with its doc_id
+pub const CRAWLED_PAGES_CHANNEL: &str = "crawled_pages";
 
 pub trait Database {
     fn set_schema(self: &mut Self) -> Result<(), Error>;
@@ -15,19 +26,29 @@ pub trait Database {
     fn urlqueue_pop_front(self: &mut Self, crawler_id: i32) -> Option<(String, i32)>;
     fn urlqueue_push(self: &mut Self, url: &str, depth: i32, crawler_id: i32) -> Result<String, Error>;
     fn crawledurls_status(self: &mut Self, url: &str) -> UsedUrlStatus;
-    fn crawledurls_add(self: &mut Self, url: &str) -> UsedUrlStatus;
+    fn crawledurls_get(self: &mut Self, url: &str) -> Option<CrawlSchedule>;
+    fn crawledurls_set(self: &mut Self, url: &str, schedule: &CrawlSchedule) -> Result<(), Error>;
+    fn crawledurls_enqueue_due(self: &mut Self, limit: i64) -> Result<u64, Error>;
+    // claims a url for the crawler about to fetch it by moving its crawl_again_at lease seconds ahead. False when the
+    // url isnt due, because another crawler holds it or it was crawled recently
+    fn crawledurls_reserve(self: &mut Self, url: &str, depth: i32, lease: i64) -> Result<bool, Error>;
+    // drops the row of a reservation that never got a schedule of its own
+    fn crawledurls_release(self: &mut Self, url: &str) -> Result<(), Error>;
+    // records that a page is gone for good and drops anything of it still waiting for the indexer.
+    // The indexer takes it out of the index. Tombstoning a page that already is one changes nothing
+    fn tombstone(self: &mut Self, url: &str) -> Result<(), Error>;
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum Error {
-    SQLError(Option<SqlState>)
+    SQLError(Option<SqlState>),
+    // (doc_id, url, the url already stored under that id)
+    DocumentIdCollision(i64, String, String)
 }
 
 pub enum UsedUrlStatus {
-    NewUrl,
     UrlDoesntExist,
-    URLExists,
     CannotCrawlUrl,
     CanCrawlUrl
 }
@@ -51,46 +72,150 @@ impl PostgresDatabase {
 
 impl Database for PostgresDatabase {
     fn set_schema(self: &mut Self) -> Result<(), Error> {
+        match self.client.batch_execute("
+            CREATE TABLE IF NOT EXISTS Documents (
+                id bigint PRIMARY KEY,
+                url text NOT NULL UNIQUE
+            );
+        ") {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        match self.migrate_to_document_ids() {
+            Ok(_) => {},
+            Err(t) => return Err(t)
+        };
+
         let result = self.client.batch_execute("
             CREATE TABLE IF NOT EXISTS CrawledData (
-                url varchar(512) PRIMARY KEY,
+                doc_id bigint PRIMARY KEY,
                 title varchar(512),
                 description varchar(1024)
             );
 
+            ALTER TABLE CrawledData
+                ADD COLUMN IF NOT EXISTS encoding varchar(32),
+                ADD COLUMN IF NOT EXISTS language varchar(16),
+                ADD COLUMN IF NOT EXISTS claimed_at bigint;
+
             CREATE TABLE IF NOT EXISTS CrawledWords (
-                url varchar(512),
+                doc_id bigint,
                 parent varchar(512),
                 word varchar(64),
                 count integer,
 
-                PRIMARY KEY (url, word, parent)
+                PRIMARY KEY (doc_id, word, parent)
             );
 
+            ALTER TABLE CrawledWords
+                ADD COLUMN IF NOT EXISTS surface varchar(512),
+                ADD COLUMN IF NOT EXISTS positions integer[];
+
             CREATE TABLE IF NOT EXISTS URLQueue (
-                url varchar(512) PRIMARY KEY,
+                doc_id bigint PRIMARY KEY,
                 depth integer,
                 crawler_id integer
             );
 
             CREATE TABLE IF NOT EXISTS CrawledURLs (
-                url varchar(512) PRIMARY KEY,
+                doc_id bigint PRIMARY KEY,
                 crawl_again_at bigint
             );
 
+            ALTER TABLE CrawledURLs
+                ADD COLUMN IF NOT EXISTS depth integer DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS content_hash bigint DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS last_crawled_at bigint DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS recrawl_interval bigint DEFAULT 604800,
+                ADD COLUMN IF NOT EXISTS fetch_count integer DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS change_count integer DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS etag varchar(512),
+                ADD COLUMN IF NOT EXISTS last_modified varchar(64),
+                ADD COLUMN IF NOT EXISTS gone_count integer DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS failure_count integer DEFAULT 0;
+
+            CREATE INDEX IF NOT EXISTS crawledurls_crawl_again_at ON CrawledURLs (crawl_again_at);
+
             CREATE TABLE IF NOT EXISTS IndexedWords (
-                url varchar(512),
+                doc_id bigint,
                 word varchar(512),
                 weight integer,
 
-                PRIMARY KEY (url, word)
+                PRIMARY KEY (doc_id, word)
+            );
+
+            ALTER TABLE IndexedWords
+                ADD COLUMN IF NOT EXISTS surface varchar(512),
+                ADD COLUMN IF NOT EXISTS positions bytea;
+
+            CREATE TABLE IF NOT EXISTS TermForms (
+                surface varchar(512),
+                stem varchar(512),
+
+                PRIMARY KEY (surface, stem)
             );
 
             CREATE TABLE IF NOT EXISTS SiteMetadata (
-                url varchar(512) PRIMARY KEY,
+                doc_id bigint PRIMARY KEY,
                 title varchar(512),
                 description varchar(1024)
             );
+
+            ALTER TABLE SiteMetadata
+                ADD COLUMN IF NOT EXISTS language varchar(16),
+                ADD COLUMN IF NOT EXISTS fingerprint bigint;
+
+            CREATE TABLE IF NOT EXISTS TermFrequencies (
+                doc_id bigint,
+                word varchar(512),
+                field varchar(512),
+                tf integer,
+
+                PRIMARY KEY (doc_id, word, field)
+            );
+
+            CREATE TABLE IF NOT EXISTS FieldLengths (
+                doc_id bigint,
+                field varchar(512),
+                length bigint,
+
+                PRIMARY KEY (doc_id, field)
+            );
+
+            CREATE TABLE IF NOT EXISTS DocumentFrequencies (
+                word varchar(512) PRIMARY KEY,
+                df integer
+            );
+
+            CREATE TABLE IF NOT EXISTS FieldStats (
+                field varchar(512) PRIMARY KEY,
+                documents bigint,
+                total_length bigint
+            );
+
+            CREATE TABLE IF NOT EXISTS Tombstones (
+                doc_id bigint PRIMARY KEY,
+                gone_at bigint,
+                removed_at bigint
+            );
+
+            CREATE TABLE IF NOT EXISTS CrawledText (
+                doc_id bigint PRIMARY KEY,
+                crawled_at bigint,
+                title varchar(512),
+                description varchar(1024),
+                language varchar(16),
+                text bytea
+            );
+
+            CREATE TABLE IF NOT EXISTS failed_index (
+                doc_id bigint PRIMARY KEY,
+                error text,
+                attempts integer,
+                failed_at bigint,
+                retry_at bigint
+            );
         ");
 
         match result {
@@ -101,51 +226,112 @@ impl Database for PostgresDatabase {
 
 
     fn write_crawled_page(self: &mut Self, page: &parser::ParsedData, url: &String) -> Result<(), Error> {
-        let mut urls: Vec<String> = vec![];
+        let doc_id = document_id(url);
+
+        let mut doc_ids: Vec<i64> = vec![];
         let mut words: Vec<String> = vec![];
+        let mut surfaces: Vec<String> = vec![];
         let mut parents: Vec<String> = vec![];
         let mut counts: Vec<i32> = vec![];
+        // postgres cant take an array of arrays with different lengths, so positions go over as a string per word
+        let mut positions: Vec<String> = vec![];
 
         for word in page.words.iter() {
-            if word.word.len() > 64 {
-                continue;
-            }
-
-            urls.push(url.clone());
+            doc_ids.push(doc_id);
             words.push(word.word.clone());
+            surfaces.push(word.surface.clone());
             parents.push(word.parent.clone());
             counts.push(word.count as i32);
+            positions.push(format!("{{{}}}", word.positions.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(",")));
             
         }
         
-        match self.client.execute(
-            "INSERT INTO crawledwords (url, parent, word, count)
-            SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::int[])
-            ON CONFLICT (url, parent, word)
-            DO UPDATE SET count = crawledwords.count + EXCLUDED.count",
-            &[&urls, &parents, &words, &counts]
+        // the text before analysis, so the indexer can reindex the page without crawling it again
+        let text = page_text::compress(&page.texts);
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64;
+
+        // the page and its words are written in one transaction, so the indexer never claims a page
+        // whose words are only half written
+        let mut transaction = match self.client.transaction() {
+            Ok(t) => t,
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        match register_document(&mut transaction, url) {
+            Ok(_) => {},
+            Err(t) => return Err(t)
+        };
+
+        // a crawl replaces the words of the last one instead of adding to them, so writing the same page twice
+        // leaves the same rows behind
+        match transaction.execute("DELETE FROM crawledwords WHERE doc_id = $1", &[&doc_id]) {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        match transaction.execute(
+            "INSERT INTO crawledwords (doc_id, parent, word, count, surface, positions)
+            SELECT doc_id, parent, word, count, surface, positions::int[] FROM UNNEST($1::bigint[], $2::text[], $3::text[], $4::int[], $5::text[], $6::text[])
+                AS t (doc_id, parent, word, count, surface, positions)
+            ON CONFLICT (doc_id, parent, word)
+            DO UPDATE SET count = EXCLUDED.count, surface = EXCLUDED.surface, positions = EXCLUDED.positions",
+            &[&doc_ids, &parents, &words, &counts, &surfaces, &positions]
         ) {
             Ok(_) => {},
             Err(t) => return Err(Error::SQLError(t.code().cloned()))
         };
-        /*  
-            This is at the end because i cant be bothered to lock the db
-            tldr: An issue appears whe the crawleddata gets written -> indexer pops new crawleddata entry ->
-            indexer only gets some words, the rest arent written yet -> 
-            orphaned words build up over time
 
-            I solved this by ensuring the words are written before giving them a parent,
-            not ideal but it works
-        */
+        // clearing claimed_at hands the new version back to the indexer, even if an older one is claimed right now
+        match transaction.execute(
+            "INSERT INTO crawleddata (doc_id, title, description, encoding, language, claimed_at) VALUES ($1, $2, $3, $4, $5, NULL)
+            ON CONFLICT (doc_id)
+            DO UPDATE SET
+                title = EXCLUDED.title,
+                description = EXCLUDED.description,
+                encoding = EXCLUDED.encoding,
+                language = EXCLUDED.language,
+                claimed_at = NULL;",
+            &[&doc_id, &page.title, &page.description, &page.encoding, &page.language]
+        ) {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
 
-        match self.client.execute(
-            "INSERT INTO crawleddata VALUES ($1, $2, $3);",
-            &[&url, &page.title, &page.description]
+        match transaction.execute(
+            "INSERT INTO crawledtext (doc_id, crawled_at, title, description, language, text) VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (doc_id)
+            DO UPDATE SET
+                crawled_at = EXCLUDED.crawled_at,
+                title = EXCLUDED.title,
+                description = EXCLUDED.description,
+                language = EXCLUDED.language,
+                text = EXCLUDED.text",
+            &[&doc_id, &now, &page.title, &page.description, &page.language, &text]
         ) {
             Ok(_) => {},
             Err(t) => return Err(Error::SQLError(t.code().cloned()))
         };
-        return Ok(())
+
+        // a new version of the page gets a fresh set of attempts from the indexer, and a page that came back isnt gone anymore
+        match transaction.execute("DELETE FROM failed_index WHERE doc_id = $1", &[&doc_id]) {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+        match transaction.execute("DELETE FROM tombstones WHERE doc_id = $1", &[&doc_id]) {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        // postgres only delivers this once the transaction commits, so the indexer never wakes up for a page it cant see yet
+        match transaction.execute("SELECT pg_notify($1, $2)", &[&CRAWLED_PAGES_CHANNEL, &doc_id.to_string()]) {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        match transaction.commit() {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
     }
 
     fn urlqueue_count(self: &mut Self) -> i64 {
@@ -162,11 +348,14 @@ impl Database for PostgresDatabase {
     fn urlqueue_pop_front(self: &mut Self, crawler_id: i32) -> Option<(String, i32)> {
         // get a url owned by the callee
         match self.client.query_one(
-            "DELETE FROM urlqueue WHERE url = (
-                SELECT url FROM urlqueue
-                WHERE crawler_id=$1 OR crawler_id=0
-                ORDER BY crawler_id DESC LIMIT 1
-            ) RETURNING url, depth",
+            "WITH popped AS (
+                DELETE FROM urlqueue WHERE doc_id = (
+                    SELECT doc_id FROM urlqueue
+                    WHERE crawler_id=$1 OR crawler_id=0
+                    ORDER BY crawler_id DESC LIMIT 1
+                ) RETURNING doc_id, depth
+            )
+            SELECT documents.url, popped.depth FROM popped JOIN documents ON documents.id = popped.doc_id",
             &[&crawler_id]
         ) {
             Ok(t) => {
@@ -183,9 +372,14 @@ impl Database for PostgresDatabase {
     }
 
     fn urlqueue_push(self: &mut Self, url: &str, depth: i32, crawler_id: i32) -> Result<String, Error> {
+        let doc_id = match self.document(url) {
+            Ok(t) => t,
+            Err(t) => return Err(t)
+        };
+
         match self.client.execute(
-            "INSERT INTO urlqueue VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
-            &[&url, &depth, &crawler_id]
+            "INSERT INTO urlqueue (doc_id, depth, crawler_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            &[&doc_id, &depth, &crawler_id]
         ) {
             Ok(_) => return Ok("Success".to_string()),
             Err(t) => return Err(Error::SQLError(t.code().cloned()))
@@ -194,8 +388,8 @@ impl Database for PostgresDatabase {
 
     fn crawledurls_status(self: &mut Self, url: &str) -> UsedUrlStatus {
         let used_url = match self.client.query_one(
-            "SELECT * FROM crawledurls WHERE url = $1",
-            &[&url]
+            "SELECT * FROM crawledurls WHERE doc_id = $1",
+            &[&document_id(url)]
         ) {
             Ok(t) => t,
             Err(_t) => return UsedUrlStatus::UrlDoesntExist
@@ -210,17 +404,366 @@ impl Database for PostgresDatabase {
         return UsedUrlStatus::CannotCrawlUrl;
     }
 
-    fn crawledurls_add(self: &mut Self, url: &str) -> UsedUrlStatus {
-        let oneweek = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() + 7*86400;
+    fn crawledurls_get(self: &mut Self, url: &str) -> Option<CrawlSchedule> {
+        let row = match self.client.query_one(
+            "SELECT * FROM crawledurls WHERE doc_id = $1",
+            &[&document_id(url)]
+        ) {
+            Ok(t) => t,
+            Err(_) => return None
+        };
+
+        return Some(CrawlSchedule {
+            depth: row.get::<&str, i32>("depth"),
+            content_hash: row.get::<&str, i64>("content_hash"),
+            last_crawled_at: row.get::<&str, i64>("last_crawled_at"),
+            crawl_again_at: row.get::<&str, i64>("crawl_again_at"),
+            recrawl_interval: row.get::<&str, i64>("recrawl_interval"),
+            fetch_count: row.get::<&str, i32>("fetch_count"),
+            change_count: row.get::<&str, i32>("change_count"),
+            gone_count: row.get::<&str, i32>("gone_count"),
+            failure_count: row.get::<&str, i32>("failure_count"),
+            validators: CacheValidators {
+                etag: row.get::<&str, Option<String>>("etag"),
+                last_modified: row.get::<&str, Option<String>>("last_modified")
+            }
+        })
+    }
+
+    fn crawledurls_set(self: &mut Self, url: &str, schedule: &CrawlSchedule) -> Result<(), Error> {
+        let doc_id = match self.document(url) {
+            Ok(t) => t,
+            Err(t) => return Err(t)
+        };
+
+        match self.client.execute(
+            "INSERT INTO crawledurls (doc_id, crawl_again_at, depth, content_hash, last_crawled_at, recrawl_interval, fetch_count, change_count, etag, last_modified, gone_count, failure_count)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            ON CONFLICT (doc_id)
+            DO UPDATE SET
+                crawl_again_at = EXCLUDED.crawl_again_at,
+                depth = EXCLUDED.depth,
+                content_hash = EXCLUDED.content_hash,
+                last_crawled_at = EXCLUDED.last_crawled_at,
+                recrawl_interval = EXCLUDED.recrawl_interval,
+                fetch_count = EXCLUDED.fetch_count,
+                change_count = EXCLUDED.change_count,
+                etag = EXCLUDED.etag,
+                last_modified = EXCLUDED.last_modified,
+                gone_count = EXCLUDED.gone_count,
+                failure_count = EXCLUDED.failure_count",
+            &[&doc_id, &schedule.crawl_again_at, &schedule.depth, &schedule.content_hash, &schedule.last_crawled_at,
+                &schedule.recrawl_interval, &schedule.fetch_count, &schedule.change_count,
+                &schedule.validators.etag, &schedule.validators.last_modified, &schedule.gone_count, &schedule.failure_count]
+        ) {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+    }
+
+    fn crawledurls_enqueue_due(self: &mut Self, limit: i64) -> Result<u64, Error> {
+        // due urls go back on the queue unowned, so any crawler can pick them up. A url that is popped again while
+        // another crawler fetches it fails crawledurls_reserve, so pushing it twice is harmless
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64;
 
-        let _used_url = match self.client.execute(
-            "INSERT INTO crawledurls VALUES ($1, $2)",
-            &[&url, &(oneweek as i64)]
+        match self.client.execute(
+            "INSERT INTO urlqueue (doc_id, depth, crawler_id)
+            SELECT doc_id, depth, 0 FROM crawledurls
+            WHERE crawl_again_at < $1
+            ORDER BY crawl_again_at LIMIT $2
+            ON CONFLICT DO NOTHING",
+            &[&now, &limit]
         ) {
+            Ok(t) => return Ok(t),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+    }
+
+    fn crawledurls_reserve(self: &mut Self, url: &str, depth: i32, lease: i64) -> Result<bool, Error> {
+        let doc_id = match self.document(url) {
             Ok(t) => t,
-            Err(_) => {return UsedUrlStatus::URLExists}
+            Err(t) => return Err(t)
         };
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64;
+
+        // one statement, so of two crawlers that popped the same url only one gets the row
+        match self.client.query(
+            "INSERT INTO crawledurls (doc_id, crawl_again_at, depth) VALUES ($1, $2, $3)
+            ON CONFLICT (doc_id)
+            DO UPDATE SET crawl_again_at = EXCLUDED.crawl_again_at
+            WHERE crawledurls.crawl_again_at < $4
+            RETURNING doc_id",
+            &[&doc_id, &(now + lease), &depth, &now]
+        ) {
+            Ok(t) => return Ok(t.len() > 0),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+    }
+
+    fn crawledurls_release(self: &mut Self, url: &str) -> Result<(), Error> {
+        match self.client.execute(
+            "DELETE FROM crawledurls WHERE doc_id = $1 AND last_crawled_at = 0 AND fetch_count = 0 AND failure_count = 0",
+            &[&document_id(url)]
+        ) {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+    }
 
-        return UsedUrlStatus::NewUrl
+    fn tombstone(self: &mut Self, url: &str) -> Result<(), Error> {
+        let doc_id = document_id(url);
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64;
+
+        let mut transaction = match self.client.transaction() {
+            Ok(t) => t,
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        let result = transaction.execute(
+            "INSERT INTO tombstones (doc_id, gone_at, removed_at) VALUES ($1, $2, NULL)
+            ON CONFLICT (doc_id) DO NOTHING",
+            &[&doc_id, &now]
+        ).and_then(|_| transaction.execute(
+            "DELETE FROM crawleddata WHERE doc_id = $1",
+            &[&doc_id]
+        )).and_then(|_| transaction.execute(
+            "DELETE FROM crawledwords WHERE doc_id = $1",
+            &[&doc_id]
+        )).and_then(|_| transaction.execute(
+            "DELETE FROM crawledtext WHERE doc_id = $1",
+            &[&doc_id]
+        )).and_then(|_| transaction.execute(
+            "DELETE FROM failed_index WHERE doc_id = $1",
+            &[&doc_id]
+        // wakes the indexer up the same way a new page does
+        )).and_then(|_| transaction.execute(
+            "SELECT pg_notify($1, $2)",
+            &[&CRAWLED_PAGES_CHANNEL, &doc_id.to_string()]
+        ));
+        match result {
+            Ok(_) => {},
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        match transaction.commit() {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+    }
+}
+
+impl PostgresDatabase {
+    // makes sure the url has a row in documents and returns its id
+    fn document(self: &mut Self, url: &str) -> Result<i64, Error> {
+        return register_document(&mut self.client, url);
+    }
+
+    // tables from before documents existed are keyed on the url string. Each one gets its urls registered in documents,
+    // a doc_id column filled from them, and its primary key moved over, all in one transaction so a failure changes nothing
+    fn migrate_to_document_ids(self: &mut Self) -> Result<(), Error> {
+        let tables: [(&str, &str); 8] = [
+            ("crawleddata", "doc_id"),
+            ("crawledwords", "doc_id, word, parent"),
+            ("urlqueue", "doc_id"),
+            ("crawledurls", "doc_id"),
+            ("indexedwords", "doc_id, word"),
+            ("sitemetadata", "doc_id"),
+            ("termfrequencies", "doc_id, word, field"),
+            ("fieldlengths", "doc_id, field"),
+        ];
+        let names: Vec<&str> = tables.iter().map(|t| t.0).collect();
+
+        let legacy: Vec<String> = match self.client.query(
+            "SELECT table_name::text FROM information_schema.columns
+            WHERE table_schema = current_schema() AND column_name = 'url' AND table_name = ANY($1)",
+            &[&names]
+        ) {
+            Ok(t) => t.iter().map(|row| row.get::<usize, String>(0)).collect(),
+            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+        };
+
+        for (table, primary_key) in tables.iter().filter(|t| legacy.iter().any(|name| name == t.0)) {
+            info!("Migrating {} to document ids", table);
+
+            let mut transaction = match self.client.transaction() {
+                Ok(t) => t,
+                Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            };
+
+            let urls: Vec<String> = match transaction.query(&format!("SELECT DISTINCT url FROM {} WHERE url IS NOT NULL", table), &[]) {
+                Ok(t) => t.iter().map(|row| row.get::<usize, String>(0)).collect(),
+                Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            };
+            let ids: Vec<i64> = urls.iter().map(|t| document_id(t)).collect();
+
+            match transaction.execute(
+                "INSERT INTO documents (id, url) SELECT * FROM UNNEST($1::bigint[], $2::text[]) ON CONFLICT DO NOTHING",
+                &[&ids, &urls]
+            ) {
+                Ok(_) => {},
+                Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            };
+
+            // a url whose id already belongs to another url finds no document below, and its rows are dropped
+            match transaction.query(
+                "SELECT t.url, documents.url FROM UNNEST($1::bigint[], $2::text[]) AS t (id, url)
+                JOIN documents ON documents.id = t.id WHERE documents.url <> t.url",
+                &[&ids, &urls]
+            ) {
+                Ok(t) => for row in t {
+                    warn!("Dropping {} from {}, its document id belongs to {}", row.get::<usize, String>(0), table, row.get::<usize, String>(1));
+                },
+                Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            };
+
+            let result = transaction.batch_execute(&format!("
+                ALTER TABLE {table} ADD COLUMN IF NOT EXISTS doc_id bigint;
+                UPDATE {table} SET doc_id = documents.id FROM documents WHERE documents.url = {table}.url;
+                DELETE FROM {table} WHERE doc_id IS NULL;
+                ALTER TABLE {table} DROP CONSTRAINT IF EXISTS {table}_pkey;
+                ALTER TABLE {table} DROP COLUMN url;
+                ALTER TABLE {table} ADD PRIMARY KEY ({primary_key});
+            "));
+
+            match result {
+                Ok(_) => {},
+                Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            };
+
+            match transaction.commit() {
+                Ok(_) => {},
+                Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            };
+        }
+
+        return Ok(());
+    }
+}
+
+// makes sure the url has a row in documents and returns its id. A url whose id is already taken by another url is refused,
+// the two would otherwise share one document
+fn register_document<C: GenericClient>(client: &mut C, url: &str) -> Result<i64, Error> {
+    let doc_id = document_id(url);
+    let inserted = match client.query_opt(
+        "INSERT INTO documents (id, url) VALUES ($1, $2) ON CONFLICT DO NOTHING RETURNING url",
+        &[&doc_id, &url]
+    ) {
+        Ok(t) => t,
+        Err(t) => return Err(Error::SQLError(t.code().cloned()))
+    };
+    if inserted.is_some() {
+        return Ok(doc_id);
+    }
+
+    // its own statement, the insert cant see a row another crawler committed while it waited on it
+    let registered = match client.query_one("SELECT url FROM documents WHERE id = $1", &[&doc_id]) {
+        Ok(t) => t.get::<usize, String>(0),
+        Err(t) => return Err(Error::SQLError(t.code().cloned()))
+    };
+    if registered != url {
+        // logged here, most callers only queue links and dont look at the error
+        warn!("Refusing {}, its document id {} belongs to {}", url, doc_id, registered);
+        return Err(Error::DocumentIdCollision(doc_id, url.to_string(), registered));
+    }
+    return Ok(doc_id);
+}
+
+// the id of a url is a hash of it, so every crawler and the indexer agree on ids without asking the database.
+// With 64 bits a collision is unlikely until there are billions of urls
+pub fn document_id(url: &str) -> i64 {
+    return xxh3_64(url.as_bytes()) as i64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs against the postgres in CRAWLER_TEST_DATABASE, a connection string like "host=localhost user=postgres", in a
+    // schema of its own. Skipped when it isnt set
+    #[test]
+    fn documents_refuse_colliding_urls() {
+        let connection = match std::env::var("CRAWLER_TEST_DATABASE") {
+            Ok(t) => t,
+            Err(_) => {
+                eprintln!("CRAWLER_TEST_DATABASE isnt set, skipping documents_refuse_colliding_urls");
+                return;
+            }
+        };
+        let mut client = Client::connect(&connection, NoTls).unwrap();
+        client.batch_execute("
+            DROP SCHEMA IF EXISTS crawler_test_documents CASCADE;
+            CREATE SCHEMA crawler_test_documents;
+            SET search_path TO crawler_test_documents;
+        ").unwrap();
+        let mut db = PostgresDatabase { client: client };
+        db.set_schema().unwrap();
+
+        let url = "https://example.com/";
+        assert_eq!(db.document(url).unwrap(), document_id(url));
+        assert_eq!(db.document(url).unwrap(), document_id(url));
+
+        // another url with the same id, as if xxh3 collided
+        let other = "https://example.com/other";
+        db.client.execute("INSERT INTO documents (id, url) VALUES ($1, $2)", &[&document_id(other), &"https://example.org/collides"]).unwrap();
+        match db.urlqueue_push(other, 0, 0) {
+            Err(Error::DocumentIdCollision(id, url, registered)) => {
+                assert_eq!((id, url.as_str(), registered.as_str()), (document_id(other), other, "https://example.org/collides"));
+            },
+            _ => panic!("expected a collision")
+        };
+        assert_eq!(db.urlqueue_count(), 0);
+
+        db.client.batch_execute("DROP SCHEMA crawler_test_documents CASCADE").unwrap();
+    }
+
+    #[test]
+    fn crawledurls_reserve_once() {
+        let connection = match std::env::var("CRAWLER_TEST_DATABASE") {
+            Ok(t) => t,
+            Err(_) => {
+                eprintln!("CRAWLER_TEST_DATABASE isnt set, skipping crawledurls_reserve_once");
+                return;
+            }
+        };
+        let mut client = Client::connect(&connection, NoTls).unwrap();
+        client.batch_execute("
+            DROP SCHEMA IF EXISTS crawler_test_reserve CASCADE;
+            CREATE SCHEMA crawler_test_reserve;
+            SET search_path TO crawler_test_reserve;
+        ").unwrap();
+        let mut db = PostgresDatabase { client: client };
+        db.set_schema().unwrap();
+
+        // a new url goes to the first crawler that asks, and a reservation nobody used is dropped again
+        let url = "https://example.com/";
+        assert!(db.crawledurls_reserve(url, 1, 600).unwrap());
+        assert!(!db.crawledurls_reserve(url, 1, 600).unwrap());
+        db.crawledurls_release(url).unwrap();
+        assert!(db.crawledurls_get(url).is_none());
+
+        // a crawled url only once it is due, and the reservation leaves its schedule alone
+        let mut schedule = CrawlSchedule {
+            depth: 2,
+            content_hash: 7,
+            last_crawled_at: 1,
+            crawl_again_at: i64::MAX,
+            recrawl_interval: 60,
+            fetch_count: 1,
+            change_count: 1,
+            gone_count: 0,
+            failure_count: 0,
+            validators: CacheValidators::default()
+        };
+        db.crawledurls_set(url, &schedule).unwrap();
+        assert!(!db.crawledurls_reserve(url, 1, 600).unwrap());
+        schedule.crawl_again_at = 0;
+        db.crawledurls_set(url, &schedule).unwrap();
+        assert!(db.crawledurls_reserve(url, 1, 600).unwrap());
+        db.crawledurls_release(url).unwrap();
+        let reserved = db.crawledurls_get(url).unwrap();
+        assert!(reserved.crawl_again_at > 0);
+        assert_eq!((reserved.depth, reserved.content_hash, reserved.fetch_count), (2, 7, 1));
+
+        db.client.batch_execute("DROP SCHEMA crawler_test_reserve CASCADE").unwrap();
     }
 }
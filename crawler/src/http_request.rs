@@ -1,31 +1,95 @@
-// Handles making http requests. This is lower level than request_handler, with the intention of working around things like 3XX and content-language 
+// Handles making http requests. This is lower level than request_handler, with the intention of working around things like 3XX and content-type 
+
+use std::io::Read;
+
+use reqwest::StatusCode;
+use reqwest::blocking::RequestBuilder;
+use reqwest::header::{HeaderMap, CONTENT_LANGUAGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, LOCATION, IF_NONE_MATCH, IF_MODIFIED_SINCE};
+use url::Url;
+use log::warn;
+
+use crate::warc;
 
 #[derive(Clone)]
 pub struct HTTPRequest {
     user_agent: String,
     // max_processing_content_size: u64
-    max_page_size: u64
+    max_page_size: u64,
+    // keep the first max_page_size bytes of a page that is too big instead of dropping it
+    truncate_oversized_pages: bool,
+    // every fetch goes to the archive when warc is enabled
+    warc: Option<warc::WarcWriter>
+}
+
+// validators from a previous response, sent back on a recrawl so the server can answer 304 Not Modified
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>
+}
+
+pub struct HTTPResponse {
+    pub content: Vec<u8>,
+    // the url after dereferencing 3XX codes
+    pub url: String,
+    // the raw content-type header, it can carry the charset of the page
+    pub content_type: Option<String>,
+    pub content_language: Option<String>,
+    pub validators: CacheValidators,
+    // the server answered 304, content is empty and the page should be treated as unchanged
+    pub not_modified: bool,
+    // the body was cut off at max_page_size
+    pub truncated: bool
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum HTTPRequestError {
     FailedToFetchURL,
-    FailedToHeadURL,
     FailedToRedirect(String),
     BadStatusCode(u16),
     MissingHeader(String),
     BadHeaderValue(String, String),
     CouldntConvertToBytes,
-    ContentLengthTooBig(u64)
+    // the content-length header
+    ContentLengthTooBig(u64),
+    // without a content-length reading stops just past max_page_size, so all we know is the body was longer than it
+    ContentLongerThan(u64)
+}
+
+impl HTTPRequestError {
+    // a 404 or 410 says the page is gone, anything else (timeouts, 5xx, rate limits) might work next time
+    pub fn is_permanent(&self) -> bool {
+        return match self {
+            HTTPRequestError::BadStatusCode(404) | HTTPRequestError::BadStatusCode(410) => true,
+            _ => false
+        };
+    }
+}
+
+// the content types the crawler parses, anything else is skipped
+pub fn is_page(content_type: &str) -> bool {
+    return content_type.contains("text/html") || content_type.contains("text/plain");
+}
+
+// whether two urls only differ in scheme and a leading www
+fn same_page(url: &str, other: &Url) -> bool {
+    let url = match Url::parse(url) {
+        Ok(t) => t,
+        Err(_) => return false
+    };
+    let host = |t: &Url| t.host_str().map(|t| t.trim_start_matches("www.").to_string());
+    return host(&url) == host(other) && url.port() == other.port() && url.path() == other.path() && url.query() == other.query();
 }
 
 impl HTTPRequest {
-    pub fn new(ua: &str) -> Self {
+    pub fn new(ua: &str, max_page_size: u64, truncate_oversized_pages: bool, warc: Option<warc::WarcWriter>) -> Self {
         return HTTPRequest{
             user_agent: ua.to_string(),
             // max_processing_content_size = 2 * 1024 * 1024; // 2mb
-            max_page_size: 15 * 1024 * 1024 // 15mb
+            max_page_size: max_page_size,
+            truncate_oversized_pages: truncate_oversized_pages,
+            warc: warc
         }
     }
     
@@ -33,24 +97,44 @@ impl HTTPRequest {
         return &self.user_agent
     }
 
-    pub fn request(&self, url: &str, depth: Option<i32>) -> Result<(Vec<u8>, String), HTTPRequestError> {
+    pub fn request(&self, url: &str, validators: Option<&CacheValidators>, depth: Option<i32>) -> Result<HTTPResponse, HTTPRequestError> {
         let current_depth = match depth {
             Some(t) => t,
             None => 0
         };
 
+        // redirects are followed by hand so we know the dereferenced url, and so a redirect is handled on the same response we read the page from
         let client = reqwest::blocking::Client::builder()
             .user_agent(self.user_agent.clone())
+            .redirect(reqwest::redirect::Policy::none())
             .build()
             .unwrap();
-    
-        let result = match client.head(url).send() {
+
+        // one GET for everything. The headers arrive before the body, so a page we dont want is dropped without downloading it
+        let request = match Self::conditional(client.get(url), validators).build() {
             Ok(t) => t,
-            Err(_) => return Err(HTTPRequestError::FailedToHeadURL)
+            Err(_) => return Err(HTTPRequestError::FailedToFetchURL)
         };
+        let exchange = self.warc.as_ref().map(|_| warc::Exchange::new(&request, &self.user_agent));
+        let content = match client.execute(request) {
+            Ok(t) => t,
+            Err(_) => return Err(HTTPRequestError::FailedToFetchURL)
+        };
+        let exchange = exchange.map(|t| t.with_response(&content));
+
+        // 304 is a 3XX code, so this has to come before following redirects
+        if content.status() == StatusCode::NOT_MODIFIED {
+            self.archive(exchange, &[], None, current_depth);
+            return Ok(Self::not_modified(url, content.headers(), validators));
+        }
+
+        if content.status().is_redirection() {
+            self.archive(exchange, &[], Some("unspecified"), current_depth);
+            if current_depth >= 5 {
+                return Err(HTTPRequestError::FailedToRedirect("Too many redirects".to_string()))
+            }
 
-        if result.status().is_redirection() && current_depth < 5{
-            let redirect_to = match result.headers().get("location") {
+            let redirect_to = match content.headers().get(LOCATION) {
                 Some(t) => match t.to_str() {
                     Ok(t) => t,
                     Err(t) => return Err(HTTPRequestError::FailedToRedirect(format!("Error getting redirect location: {}", t)))
@@ -58,56 +142,222 @@ impl HTTPRequest {
                 None => return Err(HTTPRequestError::FailedToRedirect("Couldnt find location header".to_string()))
             };
 
-            return self.request(redirect_to, Some(current_depth+1));
+            // location is allowed to be relative to the url that redirected
+            let redirect_url = match Url::parse(url).and_then(|t| t.join(redirect_to)) {
+                Ok(t) => t,
+                Err(t) => return Err(HTTPRequestError::FailedToRedirect(format!("Bad redirect location {}: {}", redirect_to, t)))
+            };
+
+            // links are queued as http, so most recrawls start with a redirect to https before reaching the page the validators
+            // came from. They go along to the same page on another scheme or with or without www, but not to a different page
+            let redirect_validators = if same_page(url, &redirect_url) { validators } else { None };
+            return self.request(redirect_url.as_str(), redirect_validators, Some(current_depth+1));
         }
 
-        if result.status().is_client_error() || result.status().is_server_error() {
-            return Err(HTTPRequestError::BadStatusCode(result.status().as_u16()));
+        if content.status().is_client_error() || content.status().is_server_error() {
+            self.archive(exchange, &[], Some("unspecified"), current_depth);
+            return Err(HTTPRequestError::BadStatusCode(content.status().as_u16()));
         }
 
-        let content_type = match result.headers().get("content-type") {
+        let content_type = match content.headers().get(CONTENT_TYPE) {
             Some(t) => t,
-            None => return Err(HTTPRequestError::MissingHeader("content-type".to_string()))
+            None => {
+                self.archive(exchange, &[], Some("unspecified"), current_depth);
+                return Err(HTTPRequestError::MissingHeader("content-type".to_string()))
+            }
         };
 
         if content_type.to_str().is_err() {
+            self.archive(exchange, &[], Some("unspecified"), current_depth);
             return Err(HTTPRequestError::MissingHeader("content-type".to_string()))
         }
 
-        if !content_type.to_str().unwrap().contains("text/html") && !content_type.to_str().unwrap().contains("text/plain"){
+        if !is_page(content_type.to_str().unwrap()) {
+            self.archive(exchange, &[], Some("unspecified"), current_depth);
             return Err(HTTPRequestError::BadHeaderValue("content-type".to_string(), content_type.to_str().unwrap_or("[invalid UTF-8]").to_string()))
         }
 
-        let content_lang = match result.headers().get("content-language") {
-            Some(t) => t.to_str(),
-            None => Ok("en")
+        if content.content_length().is_some() && content.content_length().unwrap() > self.max_page_size && !self.truncate_oversized_pages {
+            self.archive(exchange, &[], Some("length"), current_depth);
+            return Err(HTTPRequestError::ContentLengthTooBig(content.content_length().unwrap()))
+        }
+
+        let response_validators = Self::read_validators(content.headers());
+        let response_content_type = content_type.to_str().ok().map(|t| t.to_string());
+        // the language policy is applied by the crawler once it has the page text, headers alone arent trustworthy
+        let response_content_language = content.headers().get(CONTENT_LANGUAGE).and_then(|t| t.to_str().ok()).map(|t| t.to_string());
+
+        // content-length is optional and chunked responses dont have one, so the limit is also enforced while reading.
+        // Reading one byte past the limit tells us the body was too big without holding more of it in memory
+        let mut bytes: Vec<u8> = vec![];
+        match content.take(self.max_page_size + 1).read_to_end(&mut bytes) {
+            Ok(_) => {},
+            Err(_) => {
+                self.archive(exchange, &bytes, Some("disconnect"), current_depth);
+                return Err(HTTPRequestError::CouldntConvertToBytes)
+            }
         };
 
-        match content_lang {
-            Ok(t) => {
-                if t != "en" {
-                    return Err(HTTPRequestError::BadHeaderValue("content-language".to_string(), t.to_string()))
-                }
+        let truncated = bytes.len() as u64 > self.max_page_size;
+        if truncated {
+            bytes.truncate(self.max_page_size as usize);
+            self.archive(exchange, &bytes, Some("length"), current_depth);
+            if !self.truncate_oversized_pages {
+                return Err(HTTPRequestError::ContentLongerThan(self.max_page_size))
             }
-            Err(_) => {}
+        } else {
+            self.archive(exchange, &bytes, None, current_depth);
+        }
+
+        // returning the url lets us know what the actual url is when dereferencing 3XX Urls
+        return Ok(HTTPResponse {
+            content: bytes,
+            url: url.to_owned(),
+            content_type: response_content_type,
+            content_language: response_content_language,
+            validators: response_validators,
+            not_modified: false,
+            truncated: truncated
+        })
+    }
+
+    fn archive(&self, exchange: Option<warc::Exchange>, body: &[u8], truncated: Option<&str>, redirects: i32) {
+        let (writer, exchange) = match (&self.warc, exchange) {
+            (Some(writer), Some(exchange)) => (writer, exchange),
+            _ => return
+        };
+        // the archive is for debugging, a fetch doesnt fail because it couldnt be written
+        match writer.write_exchange(&exchange, body, truncated, redirects) {
+            Ok(_) => {},
+            Err(t) => warn!("Couldnt archive {} {:?}", exchange.url(), t)
         };
+    }
 
-        let content = match client.get(url).send() {
-            Ok(t) => t,
-            Err(_) => return Err(HTTPRequestError::FailedToHeadURL)
+    fn conditional(request: RequestBuilder, validators: Option<&CacheValidators>) -> RequestBuilder {
+        let mut request = request;
+        let validators = match validators {
+            Some(t) => t,
+            None => return request
         };
 
-        
-        if content.content_length().is_some() && content.content_length().unwrap() > self.max_page_size {
-            return Err(HTTPRequestError::ContentLengthTooBig(content.content_length().unwrap()))
+        match &validators.etag {
+            Some(t) => request = request.header(IF_NONE_MATCH, t),
+            None => {}
         }
+        match &validators.last_modified {
+            Some(t) => request = request.header(IF_MODIFIED_SINCE, t),
+            None => {}
+        }
+        return request;
+    }
 
-        let bytes = match content.bytes() {
-            Ok(t) => t,
-            Err(_) => return Err(HTTPRequestError::CouldntConvertToBytes)
-        };
+    fn read_validators(headers: &HeaderMap) -> CacheValidators {
+        return CacheValidators {
+            etag: headers.get(ETAG).and_then(|t| t.to_str().ok()).map(|t| t.to_string()),
+            last_modified: headers.get(LAST_MODIFIED).and_then(|t| t.to_str().ok()).map(|t| t.to_string())
+        }
+    }
 
-        // returning the url lets us know what the actual url is when dereferencing 3XX Urls
-        return Ok((bytes.to_vec(), url.to_owned()))
+    // a 304 may carry updated validators, anything it leaves out stays as it was
+    fn not_modified(url: &str, headers: &HeaderMap, previous: Option<&CacheValidators>) -> HTTPResponse {
+        let mut validators = Self::read_validators(headers);
+        match previous {
+            Some(t) => {
+                validators.etag = validators.etag.or(t.etag.clone());
+                validators.last_modified = validators.last_modified.or(t.last_modified.clone());
+            }
+            None => {}
+        }
+
+        return HTTPResponse {
+            content: vec![],
+            url: url.to_owned(),
+            content_type: None,
+            content_language: None,
+            validators: validators,
+            not_modified: true,
+            truncated: false
+        }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    // answers one connection per response and hands back the request heads it got, lowercased
+    fn serve(responses: Vec<String>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/page", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = vec![];
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    head.push(byte[0]);
+                }
+                requests.push(String::from_utf8(head).unwrap().to_lowercase());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            return requests;
+        });
+        return (url, handle);
+    }
+
+    #[test]
+    fn http_request_conditional() {
+        let validators = CacheValidators { etag: Some("\"v1\"".to_string()), last_modified: None };
+        let client = reqwest::blocking::Client::new();
+        let request = HTTPRequest::conditional(client.get("http://example.com/"), Some(&validators)).build().unwrap();
+        assert_eq!(request.headers().get(IF_NONE_MATCH).unwrap(), "\"v1\"");
+        assert!(request.headers().get(IF_MODIFIED_SINCE).is_none());
+        let request = HTTPRequest::conditional(client.get("http://example.com/"), None).build().unwrap();
+        assert!(request.headers().get(IF_NONE_MATCH).is_none());
+
+        let page = Url::parse("https://www.example.com/page?a=1").unwrap();
+        assert!(same_page("http://example.com/page?a=1", &page));
+        assert!(!same_page("http://example.com/other?a=1", &page));
+        assert!(!same_page("http://example.com/page", &page));
+
+        // the redirect goes to the same page, so the validators go along and the server can answer 304
+        let (url, server) = serve(vec![
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /page\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 304 Not Modified\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+        let response = HTTPRequest::new("test", 1024, false, None).request(&url, Some(&validators), None).unwrap();
+        let requests = server.join().unwrap();
+        assert!(requests.iter().all(|t| t.contains("if-none-match: \"v1\"")));
+        assert!(response.not_modified);
+        assert_eq!(response.url, url);
+        // a 304 keeps the validators it doesnt replace
+        assert_eq!(response.validators.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(response.validators.last_modified.as_deref(), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+    }
+
+    #[test]
+    fn http_request_page_size_limit() {
+        // no content-length, so the limit can only be noticed while reading
+        let page = |body: &str| format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n{}", body);
+
+        let (url, server) = serve(vec![page("0123456789"), page("0123456789abcdef"), page("0123456789abcdef")]);
+        let response = HTTPRequest::new("test", 10, true, None).request(&url, None, None).unwrap();
+        assert_eq!(response.content, b"0123456789");
+        assert!(!response.truncated);
+
+        let response = HTTPRequest::new("test", 10, true, None).request(&url, None, None).unwrap();
+        assert_eq!(response.content, b"0123456789");
+        assert!(response.truncated);
+
+        // reading stops one byte past the limit, so the error only says the page was longer than it
+        match HTTPRequest::new("test", 10, false, None).request(&url, None, None) {
+            Err(HTTPRequestError::ContentLongerThan(10)) => {},
+            Err(t) => panic!("expected ContentLongerThan(10), got {:?}", t),
+            Ok(_) => panic!("expected ContentLongerThan(10), got a page")
+        };
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+// Reads pages from files instead of the web, so an index can be built or a bug reproduced without network access.
+// Takes WARC files (what the crawler archives with warc.enabled, or common crawl extracts), the older ARC files, and
+// directories of .html files, gzipped or not. Only the reading is done here, the pages go through the same parsing and
+// writing as a fetched page
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::{GzDecoder, MultiGzDecoder, ZlibDecoder};
+use url::Url;
+
+pub struct IngestedPage {
+    pub url: String,
+    pub status: u16,
+    // names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub content: Vec<u8>
+}
+
+impl IngestedPage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        return self.headers.iter().find(|t| t.0 == name).map(|t| t.1.as_str());
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum IngestError {
+    IOError(std::io::Error),
+    BadRecord(String),
+    UnknownFileType(String),
+    // html files carry no url of their own
+    NoBaseUrl(String)
+}
+
+impl From<std::io::Error> for IngestError {
+    fn from(error: std::io::Error) -> Self {
+        return IngestError::IOError(error);
+    }
+}
+
+// calls on_page with every http response in a warc or arc file, or every .html file under a directory. Html files get
+// their url from base_url joined with their path, a file:// url would be stored and its links queued like any other page
+pub fn read_path(path: &Path, base_url: Option<&Url>, on_page: &mut dyn FnMut(IngestedPage)) -> Result<(), IngestError> {
+    if path.is_dir() {
+        return match base_url {
+            Some(t) => read_html_dir(path, path, t, on_page),
+            None => Err(IngestError::NoBaseUrl(path.display().to_string()))
+        };
+    }
+
+    let name = path.file_name().map(|t| t.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let name = name.trim_end_matches(".gz");
+    if name.ends_with(".warc") {
+        return read_warc(&mut open(path)?, on_page);
+    }
+    if name.ends_with(".arc") {
+        return read_arc(&mut open(path)?, on_page);
+    }
+    if name.ends_with(".html") || name.ends_with(".htm") {
+        let base_url = match base_url {
+            Some(t) => t,
+            None => return Err(IngestError::NoBaseUrl(path.display().to_string()))
+        };
+        on_page(read_html_file(path, path.parent().unwrap_or(path), base_url)?);
+        return Ok(());
+    }
+    return Err(IngestError::UnknownFileType(path.display().to_string()));
+}
+
+// every record of an archive is usually its own gzip member, MultiGzDecoder reads them all as one stream
+fn open(path: &Path) -> Result<Box<dyn BufRead>, IngestError> {
+    let mut file = BufReader::new(File::open(path)?);
+    let gzipped = file.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    return match gzipped {
+        true => Ok(Box::new(BufReader::new(MultiGzDecoder::new(file)))),
+        false => Ok(Box::new(file))
+    };
+}
+
+pub fn read_warc(reader: &mut dyn BufRead, on_page: &mut dyn FnMut(IngestedPage)) -> Result<(), IngestError> {
+    loop {
+        // records are followed by two blank lines
+        let version = match read_line(reader)? {
+            Some(t) if t.is_empty() => continue,
+            Some(t) => t,
+            None => return Ok(())
+        };
+        if !version.starts_with("WARC/") {
+            return Err(IngestError::BadRecord(format!("Expected a warc record, got '{}'", version)));
+        }
+
+        let mut headers = vec![];
+        loop {
+            match read_line(reader)? {
+                Some(t) if t.is_empty() => break,
+                Some(t) => match t.split_once(':') {
+                    Some((name, value)) => headers.push((name.trim().to_lowercase(), value.trim().to_string())),
+                    None => {}
+                },
+                None => return Err(IngestError::BadRecord(String::from("Warc record ends in its headers")))
+            };
+        }
+        let header = |name: &str| headers.iter().find(|t| t.0 == name).map(|t| t.1.as_str());
+
+        let length = match header("content-length").and_then(|t| t.parse::<usize>().ok()) {
+            Some(t) => t,
+            None => return Err(IngestError::BadRecord(String::from("Warc record without a content-length")))
+        };
+        let mut block = vec![0; length];
+        reader.read_exact(&mut block)?;
+
+        // requests, metadata and the like are skipped, only responses are pages
+        let is_response = header("warc-type") == Some("response") && header("content-type").unwrap_or("").starts_with("application/http");
+        let url = header("warc-target-uri").unwrap_or("").trim_start_matches('<').trim_end_matches('>').to_string();
+        if !is_response || url.is_empty() {
+            continue;
+        }
+        match parse_http_response(url, &block) {
+            Some(t) => on_page(t),
+            None => {}
+        };
+    }
+}
+
+// arc v1: a line of "url ip date content-type length" and then length bytes, which for http urls are the whole response
+pub fn read_arc(reader: &mut dyn BufRead, on_page: &mut dyn FnMut(IngestedPage)) -> Result<(), IngestError> {
+    loop {
+        let line = match read_line(reader)? {
+            Some(t) if t.is_empty() => continue,
+            Some(t) => t,
+            None => return Ok(())
+        };
+        let fields: Vec<&str> = line.split(' ').collect();
+        let length = match fields.last().and_then(|t| t.parse::<usize>().ok()) {
+            Some(t) if fields.len() >= 5 => t,
+            _ => return Err(IngestError::BadRecord(format!("Expected an arc header, got '{}'", line)))
+        };
+        let mut block = vec![0; length];
+        reader.read_exact(&mut block)?;
+
+        // the first record describes the file itself
+        if fields[0].starts_with("filedesc://") || !fields[0].starts_with("http") {
+            continue;
+        }
+        match parse_http_response(fields[0].to_string(), &block) {
+            Some(t) => on_page(t),
+            None => {}
+        };
+    }
+}
+
+// None for anything that doesnt look like an http response. The body is undone of chunked transfer encoding and gzip
+// or deflate content encoding, whichever of them the archive kept
+fn parse_http_response(url: String, block: &[u8]) -> Option<IngestedPage> {
+    let (head, body) = match find(block, b"\r\n\r\n") {
+        Some(t) => (&block[..t], &block[t + 4..]),
+        None => match find(block, b"\n\n") {
+            Some(t) => (&block[..t], &block[t + 2..]),
+            None => (block, &block[block.len()..])
+        }
+    };
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.lines();
+
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.split_whitespace().nth(1)?.parse::<u16>().ok()?;
+
+    let headers: Vec<(String, String)> = lines.filter_map(|t| t.split_once(':')).map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string())).collect();
+    let header = |name: &str| headers.iter().find(|t| t.0 == name).map(|t| t.1.to_lowercase());
+
+    let mut content = body.to_vec();
+    if header("transfer-encoding").is_some_and(|t| t.contains("chunked")) {
+        content = dechunk(&content);
+    }
+    let decoded = match header("content-encoding").as_deref() {
+        Some("gzip") | Some("x-gzip") => {
+            let mut decoded = vec![];
+            GzDecoder::new(content.as_slice()).read_to_end(&mut decoded).ok().map(|_| decoded)
+        },
+        Some("deflate") => {
+            let mut decoded = vec![];
+            ZlibDecoder::new(content.as_slice()).read_to_end(&mut decoded).ok().map(|_| decoded)
+        },
+        _ => None
+    };
+    match decoded {
+        Some(t) => content = t,
+        None => {}
+    }
+
+    return Some(IngestedPage {
+        url: url,
+        status: status,
+        headers: headers,
+        content: content
+    });
+}
+
+// whatever came before a broken chunk is kept
+fn dechunk(body: &[u8]) -> Vec<u8> {
+    let mut content = vec![];
+    let mut rest = body;
+    loop {
+        let line_end = match find(rest, b"\r\n") {
+            Some(t) => t,
+            None => return content
+        };
+        let size = String::from_utf8_lossy(&rest[..line_end]);
+        let size = match usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16) {
+            Ok(t) => t,
+            Err(_) => return content
+        };
+        rest = &rest[line_end + 2..];
+        if size == 0 || size > rest.len() {
+            content.extend_from_slice(&rest[..size.min(rest.len())]);
+            return content;
+        }
+        content.extend_from_slice(&rest[..size]);
+        rest = rest.get(size + 2..).unwrap_or(&[]);
+    }
+}
+
+fn read_html_dir(root: &Path, dir: &Path, base_url: &Url, on_page: &mut dyn FnMut(IngestedPage)) -> Result<(), IngestError> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.filter_map(|t| t.ok()).map(|t| t.path()).collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            read_html_dir(root, &path, base_url, on_page)?;
+            continue;
+        }
+        let extension = path.extension().map(|t| t.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if extension == "html" || extension == "htm" {
+            on_page(read_html_file(&path, root, base_url)?);
+        }
+    }
+    return Ok(());
+}
+
+fn read_html_file(path: &Path, root: &Path, base_url: &Url) -> Result<IngestedPage, IngestError> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let relative: Vec<String> = relative.components().map(|t| t.as_os_str().to_string_lossy().to_string()).collect();
+    let url = match base_url.join(&relative.join("/")) {
+        Ok(t) => t.to_string(),
+        Err(t) => return Err(IngestError::BadRecord(format!("Couldnt join {} to {}: {}", path.display(), base_url, t)))
+    };
+
+    return Ok(IngestedPage {
+        url: url,
+        status: 200,
+        headers: vec![(String::from("content-type"), String::from("text/html"))],
+        content: fs::read(path)?
+    });
+}
+
+// None at the end of the input. Archive headers are ascii, anything else is replaced
+fn read_line(reader: &mut dyn BufRead) -> Result<Option<String>, IngestError> {
+    let mut line = vec![];
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    return Ok(Some(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string()));
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    return haystack.windows(needle.len()).position(|t| t == needle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warc_record(headers: &str, block: &str) -> String {
+        return format!("WARC/1.1\r\n{}Content-Length: {}\r\n\r\n{}\r\n\r\n", headers, block.len(), block);
+    }
+
+    #[test]
+    fn ingest_warc_and_arc() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nTransfer-Encoding: chunked\r\n\r\n6\r\n<html>\r\n7\r\n</html>\r\n0\r\n\r\n";
+        let warc = [
+            warc_record("WARC-Type: warcinfo\r\n", "software: test\r\n"),
+            warc_record("WARC-Type: request\r\nWARC-Target-URI: http://example.com/\r\nContent-Type: application/http;msgtype=request\r\n", "GET / HTTP/1.1\r\n\r\n"),
+            warc_record("WARC-Type: response\r\nWARC-Target-URI: <http://example.com/>\r\nContent-Type: application/http;msgtype=response\r\n", response),
+        ].concat();
+
+        let mut pages = vec![];
+        read_warc(&mut warc.as_bytes(), &mut |t| pages.push(t)).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].url, "http://example.com/");
+        assert_eq!(pages[0].status, 200);
+        assert_eq!(pages[0].header("content-type"), Some("text/html"));
+        assert_eq!(pages[0].content, b"<html></html>");
+
+        let block = "HTTP/1.0 404 Not Found\nContent-Type: text/html\n\ngone";
+        let arc = format!("filedesc://test.arc 0.0.0.0 20000101000000 text/plain 3\nabc\nhttp://example.com/a 1.2.3.4 20000101000000 text/html {}\n{}\n", block.len(), block);
+        let mut pages = vec![];
+        read_arc(&mut arc.as_bytes(), &mut |t| pages.push(t)).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].url, "http://example.com/a");
+        assert_eq!(pages[0].status, 404);
+        assert_eq!(pages[0].content, b"gone");
+
+        assert!(read_warc(&mut "not a warc".as_bytes(), &mut |_| {}).is_err());
+    }
+
+    #[test]
+    fn ingest_html_dir() {
+        let dir = std::env::temp_dir().join(format!("crawler-ingest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        fs::write(dir.join("docs").join("a b.htm"), "<html></html>").unwrap();
+        fs::write(dir.join("notes.txt"), "not a page").unwrap();
+
+        // without a base url there is no url to store the pages under
+        let mut urls: Vec<String> = vec![];
+        assert!(matches!(read_path(&dir, None, &mut |t| urls.push(t.url)), Err(IngestError::NoBaseUrl(_))));
+        assert!(matches!(read_path(&dir.join("index.html"), None, &mut |t| urls.push(t.url)), Err(IngestError::NoBaseUrl(_))));
+        assert!(urls.is_empty());
+
+        let base_url = Url::parse("https://example.com/site/").unwrap();
+        read_path(&dir, Some(&base_url), &mut |t| urls.push(t.url)).unwrap();
+        assert_eq!(urls, vec!["https://example.com/site/docs/a%20b.htm", "https://example.com/site/index.html"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
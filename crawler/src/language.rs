@@ -0,0 +1,111 @@
+// Decides what language a page is in and whether we want it. Headers and <html lang> are often missing or wrong
+// (a lot of sites send "en" on every page regardless of content), so a statistical detector run on the page text
+// gets the final say when it is confident. Languages are BCP-47 tags, and the allowlist matches by prefix, so "en" allows "en-US"
+
+use whatlang::Lang;
+
+// BCP-47 for "undetermined", used when nothing tells us the language
+pub const UNDETERMINED: &str = "und";
+
+#[derive(Clone)]
+pub struct LanguagePolicy {
+    allowed: Vec<String>
+}
+
+impl LanguagePolicy {
+    pub fn new(allowed: &[String]) -> Self {
+        return LanguagePolicy {
+            allowed: allowed.iter().map(|t| t.trim().to_lowercase()).collect()
+        }
+    }
+
+    // an empty allowlist allows everything. Pages we couldnt work out a language for are let through, like before there was a policy
+    pub fn allows(&self, language: &str) -> bool {
+        if self.allowed.is_empty() || language == UNDETERMINED {
+            return true;
+        }
+
+        let language = language.to_lowercase();
+        return self.allowed.iter().any(|allowed| {
+            language == *allowed || (language.starts_with(allowed.as_str()) && language[allowed.len()..].starts_with('-'))
+        });
+    }
+}
+
+// content-language can list several languages, "en, fr" means the page is meant for both
+pub fn parse_declared(content_language: Option<&str>, html_lang: Option<&str>) -> Vec<String> {
+    let mut declared: Vec<String> = vec![];
+
+    match content_language {
+        Some(t) => declared.extend(t.split(',').map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty())),
+        None => {}
+    }
+    match html_lang {
+        Some(t) if !t.trim().is_empty() => declared.push(t.trim().to_lowercase()),
+        _ => {}
+    }
+
+    return declared;
+}
+
+pub fn resolve(declared: &[String], text: &str) -> String {
+    match whatlang::detect(text) {
+        Some(t) if t.is_reliable() => return to_bcp47(t.lang()).to_string(),
+        _ => {}
+    }
+
+    return match declared.first() {
+        Some(t) => t.clone(),
+        None => UNDETERMINED.to_string()
+    };
+}
+
+// whatlang speaks ISO 639-3, everything else in the crawler speaks the 2 letter codes from BCP-47
+fn to_bcp47(lang: Lang) -> &'static str {
+    return match lang.code() {
+        "afr" => "af", "aka" => "ak", "amh" => "am", "ara" => "ar", "aze" => "az",
+        "bel" => "be", "ben" => "bn", "bul" => "bg", "cat" => "ca", "ces" => "cs",
+        "cmn" => "zh", "dan" => "da", "deu" => "de", "ell" => "el", "eng" => "en",
+        "epo" => "eo", "est" => "et", "fin" => "fi", "fra" => "fr", "guj" => "gu",
+        "heb" => "he", "hin" => "hi", "hrv" => "hr", "hun" => "hu", "hye" => "hy",
+        "ind" => "id", "ita" => "it", "jav" => "jv", "jpn" => "ja", "kan" => "kn",
+        "kat" => "ka", "khm" => "km", "kor" => "ko", "lat" => "la", "lav" => "lv",
+        "lit" => "lt", "mal" => "ml", "mar" => "mr", "mkd" => "mk", "mya" => "my",
+        "nep" => "ne", "nld" => "nl", "nob" => "nb", "ori" => "or", "pan" => "pa",
+        "pes" => "fa", "pol" => "pl", "por" => "pt", "ron" => "ro", "rus" => "ru",
+        "sin" => "si", "slk" => "sk", "slv" => "sl", "sna" => "sn", "spa" => "es",
+        "srp" => "sr", "swe" => "sv", "tam" => "ta", "tel" => "te", "tgl" => "tl",
+        "tha" => "th", "tuk" => "tk", "tur" => "tr", "ukr" => "uk", "urd" => "ur",
+        "uzb" => "uz", "vie" => "vi", "yid" => "yi", "zul" => "zu",
+        _ => UNDETERMINED
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_policy_prefix() {
+        let policy = LanguagePolicy::new(&["en".to_string(), "pt-BR".to_string()]);
+
+        assert_eq!(policy.allows("en"), true);
+        assert_eq!(policy.allows("en-US"), true);
+        assert_eq!(policy.allows("eng"), false);
+        assert_eq!(policy.allows("pt-br"), true);
+        assert_eq!(policy.allows("pt"), false);
+        assert_eq!(policy.allows("fr"), false);
+        assert_eq!(policy.allows(UNDETERMINED), true);
+    }
+
+    #[test]
+    fn language_detection_overrides_headers() {
+        let declared = parse_declared(Some("en, fr"), Some("en-US"));
+        assert_eq!(declared, vec!["en", "fr", "en-us"]);
+
+        let german = "Die Katze sitzt auf der Matte und schaut aus dem Fenster, weil draußen die Vögel singen und der Wind weht.";
+        assert_eq!(resolve(&declared, german), "de");
+        assert_eq!(resolve(&declared, ""), "en");
+        assert_eq!(resolve(&[], ""), UNDETERMINED);
+    }
+}
@@ -9,6 +9,14 @@ mod request_handler;
 mod parser;
 mod database;
 mod config;
+mod scheduler;
+mod charset;
+mod language;
+mod warc;
+mod ingest;
+
+// how far ahead a crawler moves crawl_again_at of the url it is fetching. One that dies part way holds the url this long
+const RESERVATION_SECONDS: i64 = 600;
 
 fn main() {
     let conf = config::Config::read_from_file("../config/config.yaml");
@@ -22,12 +30,40 @@ fn main() {
 
     info!("Initializing {} crawler threads with a max depth of {}, and a seed url of {}", conf.crawler.crawler_threads, conf.crawler.max_crawl_depth, conf.crawler.seed_url);
     
-    let httprequest: http_request::HTTPRequest = http_request::HTTPRequest::new(&conf.crawler.user_agent);
+    let warc = match conf.crawler.warc.enabled {
+        true => Some(warc::WarcWriter::new(&conf.crawler.warc, &conf.crawler.user_agent).expect("Couldnt create the warc directory")),
+        false => None
+    };
+    let httprequest: http_request::HTTPRequest = http_request::HTTPRequest::new(&conf.crawler.user_agent, conf.crawler.max_page_size, conf.crawler.truncate_oversized_pages, warc);
     let database: &mut dyn database::Database = &mut database::PostgresDatabase::new(&conf.database);
     
+    // a schema that couldnt be set up or migrated would only fail later in less obvious ways
     match database.set_schema() {
         Ok(()) => {info!("Initialized DB Schema");}
-        Err(_) => {}
+        Err(t) => {
+            error!("Couldnt set up the database schema {:?}", t);
+            std::process::exit(1);
+        }
+    };
+
+    let scheduler = scheduler::Scheduler::new(&conf.crawler.recrawl);
+    let language_policy = language::LanguagePolicy::new(&conf.crawler.languages);
+    let analyzers = analysis::Analyzers::new(&conf.analysis).expect("Couldnt build the analyzers from the analysis config");
+
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|t| t.as_str()) {
+        Some("ingest") => {
+            match ingest(database, &scheduler, &language_policy, &analyzers, &conf.crawler, &args[2..]) {
+                Ok(_) => {},
+                Err(t) => eprintln!("{}", t)
+            };
+            return;
+        },
+        Some(t) => {
+            eprintln!("Unknown command '{}'", t);
+            return;
+        },
+        None => {}
     };
 
     if database.urlqueue_count() == 0 {
@@ -37,12 +73,22 @@ fn main() {
 
     let mut threads = vec![];
 
+    // the scheduler thread puts urls that are due for a recrawl back on the queue
+    let db_conf = conf.database.clone();
+    let check_interval = conf.crawler.recrawl.check_interval;
+    thread::spawn(move || {
+        scheduler_thread(db_conf, check_interval);
+    });
+
     // I start at 1 because a url with a crawler id 0 in the database means it unassigned to a crawler
     for i in 1..conf.crawler.crawler_threads+1 {
         let http_clone = httprequest.clone();
         let db_conf = conf.database.clone();
+        let scheduler_clone = scheduler.clone();
+        let language_policy_clone = language_policy.clone();
+        let analyzers_clone = analyzers.clone();
         threads.push(thread::spawn(move || {
-            crawler_thread(db_conf, http_clone, i, conf.crawler.max_crawl_depth);
+            crawler_thread(db_conf, http_clone, scheduler_clone, language_policy_clone, analyzers_clone, i, conf.crawler.max_crawl_depth);
         }))
     }
 
@@ -51,8 +97,21 @@ fn main() {
     }
 }
 
+fn scheduler_thread(db_conf: config::PostgresDBInfo, check_interval: u64) {
+    let database: &mut dyn database::Database = &mut database::PostgresDatabase::new(&db_conf);
+
+    loop {
+        match database.crawledurls_enqueue_due(1000) {
+            Ok(0) => {},
+            Ok(t) => info!("Scheduler | Queued {} url(s) for a recrawl", t),
+            Err(t) => warn!("Scheduler | Couldnt queue recrawls {:?}", t)
+        }
+        std::thread::sleep(std::time::Duration::from_secs(check_interval));
+    }
+}
+
 // a crawler thread handles one domain at a time. once done, it grabs a new domain unassigned to a crawler from the queue
-fn crawler_thread(db_conf: config::PostgresDBInfo, httprequest: http_request::HTTPRequest, crawler_id: i32, max_crawl_depth: i32) {
+fn crawler_thread(db_conf: config::PostgresDBInfo, httprequest: http_request::HTTPRequest, scheduler: scheduler::Scheduler, language_policy: language::LanguagePolicy, analyzers: analysis::Analyzers, crawler_id: i32, max_crawl_depth: i32) {
     
     let robotstxt: &mut dyn robots_txt::RobotsTXT = &mut robots_txt::RobotsTXTCrate::new(httprequest.clone());
     let requesthandler: &mut dyn request_handler::RequestHandler = &mut request_handler::SimpleRequestHandler::new(robotstxt, &httprequest);
@@ -86,118 +145,341 @@ fn crawler_thread(db_conf: config::PostgresDBInfo, httprequest: http_request::HT
             continue;
         }
 
-        let page_content: Vec<u8>;
-        let dereferenced_url: String;
-        
-        match requesthandler.fetch(&url) {
+        // a url we have crawled before sends back its etag and last-modified, so an unchanged page costs a 304 instead of a full download
+        // the schedule says which url the page was stored under, a failed fetch updates that one
+        let (stored_url, mut previous_schedule) = match find_schedule(database, &url) {
+            Some((stored_url, schedule)) => (stored_url, Some(schedule)),
+            None => (url.clone(), None)
+        };
+        let validators = previous_schedule.as_ref().map(|t| &t.validators);
+
+        // the url is claimed before the fetch, so another crawler that popped it too leaves it alone
+        match database.crawledurls_reserve(&stored_url, depth, RESERVATION_SECONDS) {
+            Ok(true) => {},
+            Ok(false) => {
+                debug!("{}  | {} is being crawled or isnt due, skipping", crawler_id, stored_url);
+                continue;
+            },
+            Err(t) => {
+                warn!("{}  | Couldnt reserve {} {:?}", crawler_id, stored_url, t);
+                continue;
+            }
+        };
+
+        let response: http_request::HTTPResponse = match requesthandler.fetch(&url, validators) {
             Ok(t) => {
-                page_content = t.0;
-                dereferenced_url = t.1;
-                debug!("{}  | Fetched {}", crawler_id, dereferenced_url);
+                debug!("{}  | Fetched {}", crawler_id, t.url);
+                if t.truncated {
+                    debug!("{}  | {} was too big and got truncated to {} bytes", crawler_id, t.url, t.content.len());
+                }
+                t
             }
             Err(t) => {
                 debug!("{}  | Error fetching URL {}: {:?}", crawler_id, url, t);
-                continue;
-            },
-        }
 
-        // in normal circumstances this wouldnt run, but just incase
-        // there is an edge case where a url may not lose its qstring and fragment, causing it to be re queried
-        match database.crawledurls_add(&dereferenced_url) {
-            database::UsedUrlStatus::NewUrl => {},
-            database::UsedUrlStatus::URLExists => {
-                debug!("URL Already crawled: {}", dereferenced_url);
+                // only pages we crawled before can be in the index, a url that was never there has nothing to remove
+                match (t.is_permanent(), previous_schedule) {
+                    (true, Some(previous)) => {
+                        let (schedule, tombstoned) = scheduler.gone(&previous);
+                        match database.crawledurls_set(&stored_url, &schedule) {
+                            Ok(_) => {},
+                            Err(t) => warn!("{}  | Couldnt update schedule for {} {:?}", crawler_id, stored_url, t)
+                        };
+                        if tombstoned {
+                            info!("{}  | {} is gone, removing it from the index", crawler_id, stored_url);
+                            match database.tombstone(&stored_url) {
+                                Ok(_) => {},
+                                Err(t) => warn!("{}  | Couldnt tombstone {} {:?}", crawler_id, stored_url, t)
+                            };
+                        }
+                    },
+                    (_, previous) => schedule_failure(database, &scheduler, crawler_id, &stored_url, previous.as_ref(), depth)
+                };
                 continue;
             },
-            _ => {}
+        };
+        let dereferenced_url: String = response.url.clone();
+
+        // the page is stored under the url the fetch ended at. That url has to be claimed too, and the one we
+        // reserved gets its schedule back
+        if dereferenced_url != stored_url {
+            release(database, crawler_id, &stored_url, previous_schedule.as_ref());
+            previous_schedule = database.crawledurls_get(&dereferenced_url);
+            match database.crawledurls_reserve(&dereferenced_url, depth, RESERVATION_SECONDS) {
+                Ok(true) => {},
+                Ok(false) => {
+                    debug!("{}  | {} is being crawled or isnt due, skipping", crawler_id, dereferenced_url);
+                    continue;
+                },
+                Err(t) => {
+                    warn!("{}  | Couldnt reserve {} {:?}", crawler_id, dereferenced_url, t);
+                    continue;
+                }
+            };
         }
-        
-        let parsed_content: parser::ParsedData = match parser::parse_html(page_content, &dereferenced_url) {
-            Ok(t) => t,
-            Err(t) => { 
-                trace!("Bad parse: {:?}", t);
-                continue
+
+        if response.not_modified {
+            match previous_schedule {
+                Some(previous) => {
+                    let schedule = scheduler.not_modified(&previous, depth, response.validators);
+                    debug!("{}  | {} not modified, next crawl in {}s", crawler_id, dereferenced_url, schedule.recrawl_interval);
+                    match database.crawledurls_set(&dereferenced_url, &schedule) {
+                        Ok(_) => {},
+                        Err(t) => warn!("{}  | Couldnt update schedule for {} {:?}", crawler_id, dereferenced_url, t)
+                    }
+                }
+                None => release(database, crawler_id, &dereferenced_url, None)
             }
-        };
+            continue;
+        }
 
-        let dereferenced_url_object = match Url::parse(&dereferenced_url) {
-            Ok(t) => t,
-            Err(_) => { 
-                trace!("Failed to convert dereferenced url to url object");
-                continue;
+        process_page(database, &scheduler, &language_policy, &analyzers, crawler_id, response, previous_schedule.as_ref(), depth, max_crawl_depth);
+
+        std::thread::sleep(std::time::Duration::from_secs(5));
+    }
+
+    error!("Crawler {} had no urls for 5 loops, exiting...", crawler_id);
+}
+
+// crawler ingest [--base-url https://example.com/] <file.warc[.gz] | file.arc[.gz] | directory>...
+//
+// writes pages from archives or html files the same way a crawl would, without fetching anything. Their links are
+// queued as usual, so the next crawl picks up where the files left off. Html files need --base-url for their urls
+fn ingest(database: &mut dyn database::Database, scheduler: &scheduler::Scheduler, language_policy: &language::LanguagePolicy, analyzers: &analysis::Analyzers, conf: &config::CrawlerConfig, args: &[String]) -> Result<(), String> {
+    let usage = "usage: crawler ingest [--base-url https://example.com/] <file.warc[.gz] | file.arc[.gz] | directory>...";
+    let (base_url, paths) = match args.first().map(|t| t.as_str()) {
+        Some("--base-url") => match args.get(1).map(|t| Url::parse(t)) {
+            Some(Ok(t)) if t.scheme() == "http" || t.scheme() == "https" => (Some(t), &args[2..]),
+            Some(Ok(t)) => return Err(format!("Base url {} isnt http or https\n{}", t, usage)),
+            Some(Err(t)) => return Err(format!("Bad base url {}\n{}", t, usage)),
+            None => return Err(usage.to_string())
+        },
+        _ => (None, args)
+    };
+    if paths.len() == 0 {
+        return Err(usage.to_string());
+    }
+
+    for path in paths {
+        let mut pages = 0;
+        let mut skipped = 0;
+        let result = ingest::read_path(std::path::Path::new(path), base_url.as_ref(), &mut |mut page| {
+            let content_type = page.header("content-type").map(|t| t.to_string());
+            // what the fetch would have turned away
+            if !(200..300).contains(&page.status) || !content_type.as_deref().is_some_and(http_request::is_page) {
+                skipped += 1;
+                return;
             }
-        };
 
-        trace!("{}  | Finished post fetch", crawler_id);
+            let mut content = std::mem::take(&mut page.content);
+            let truncated = content.len() as u64 > conf.max_page_size;
+            if truncated {
+                if !conf.truncate_oversized_pages {
+                    skipped += 1;
+                    return;
+                }
+                content.truncate(conf.max_page_size as usize);
+            }
 
-        for raw_crawled_url in &parsed_content.urls {
-            // Tries to parse a url. if it gets something like "/domains", it fails and then tries to join the path to the parent url,
-            // so it would spit out "iana.org/domains". It double fails on fragments (good thing, they are stupid anyways). Part of me 
-            // wants to make this an if statement but idiomatic code has corrupted me.
-            let crawled_url = match Url::parse(raw_crawled_url) {
+            // a fetch stores a page under the url it ended up at, which keeps its scheme and query, so only the fragment goes
+            let url = match Url::parse(&page.url) {
                 Ok(mut t) => {
-                    filter_url(&mut t);
-                    t
+                    t.set_fragment(None);
+                    t.to_string()
                 },
-                Err(_t) => {
-                    match dereferenced_url_object.join(raw_crawled_url) {
-                        Ok(mut t) => {
-                            filter_url(&mut t);
-                            t
-                        },
-                        Err(_t) => continue
-                    }
+                Err(_) => {
+                    skipped += 1;
+                    return;
                 }
             };
-
-            match database.crawledurls_status(crawled_url.as_str()) {
-                database::UsedUrlStatus::CannotCrawlUrl => {continue;}
-                _ => {}
+            let previous_schedule = database.crawledurls_get(&url);
+            let response = http_request::HTTPResponse {
+                content: content,
+                url: url,
+                content_type: content_type,
+                content_language: page.header("content-language").map(|t| t.to_string()),
+                validators: http_request::CacheValidators {
+                    etag: page.header("etag").map(|t| t.to_string()),
+                    last_modified: page.header("last-modified").map(|t| t.to_string())
+                },
+                not_modified: false,
+                truncated: truncated
             };
+            process_page(database, scheduler, language_policy, analyzers, 0, response, previous_schedule.as_ref(), 0, conf.max_crawl_depth);
+            pages += 1;
+        });
 
-            if crawled_url.scheme() != "https" && crawled_url.scheme() != "http" {
-                debug!("Invalid schema on {}", crawled_url.as_str());
-                continue;
-            }
+        match result {
+            Ok(_) => info!("Ingested {} page(s) from {}, skipped {}", pages, path, skipped),
+            Err(t) => return Err(format!("Couldnt ingest {} after {} page(s) {:?}", path, pages, t))
+        };
+    }
+    return Ok(());
+}
 
-            // no host, no index
-            let crawled_url_host: &str = match crawled_url.domain() {
-                Some(t) => t,
-                None => continue
-            };
+// everything after the fetch: parses the page, queues its links and writes it and its schedule, unless the page is in a
+// language we dont crawl or didnt change since the last crawl. Pages ingested from files come through here too
+fn process_page(database: &mut dyn database::Database, scheduler: &scheduler::Scheduler, language_policy: &language::LanguagePolicy, analyzers: &analysis::Analyzers, crawler_id: i32,
+    response: http_request::HTTPResponse, previous_schedule: Option<&scheduler::CrawlSchedule>, depth: i32, max_crawl_depth: i32) {
+    let dereferenced_url = &response.url;
+    let mut parsed_content: parser::ParsedData = match parser::parse_html(response.content, response.content_type.as_deref(), dereferenced_url) {
+        Ok(t) => t,
+        Err(t) => { 
+            trace!("Bad parse: {:?}", t);
+            schedule_failure(database, scheduler, crawler_id, dereferenced_url, previous_schedule, depth);
+            return
+        }
+    };
+
+    let declared_languages = language::parse_declared(response.content_language.as_deref(), parsed_content.html_lang.as_deref());
+    parsed_content.language = language::resolve(&declared_languages, &parsed_content.text_sample);
+    parser::analyze(&mut parsed_content, analyzers);
+
+    let (schedule, changed) = scheduler.next(previous_schedule, depth, scheduler::content_hash(&parsed_content), response.validators);
+
+    // a page in a language we dont crawl still gets a schedule, otherwise every link to it would fetch it again
+    if !language_policy.allows(&parsed_content.language) {
+        debug!("{}  | {} is in {}, skipping", crawler_id, dereferenced_url, parsed_content.language);
+        match database.crawledurls_set(dereferenced_url, &schedule) {
+            Ok(_) => {},
+            Err(t) => warn!("{}  | Couldnt update schedule for {} {:?}", crawler_id, dereferenced_url, t)
+        }
+        return;
+    }
+
+    // an unchanged page has nothing new to index and its links were already queued last time, so only the schedule moves
+    if !changed {
+        debug!("{}  | {} unchanged, next crawl in {}s", crawler_id, dereferenced_url, schedule.recrawl_interval);
+        match database.crawledurls_set(dereferenced_url, &schedule) {
+            Ok(_) => {},
+            Err(t) => warn!("{}  | Couldnt update schedule for {} {:?}", crawler_id, dereferenced_url, t)
+        }
+        return;
+    }
 
-            if crawled_url_host == dereferenced_url_object.domain().unwrap() {
-                // has to be nested since we dont want depth above max being put on the queue
-                if depth + 1 <= max_crawl_depth {
-                    // add the url to the queue, and set the id of the crawler responsible for it. One crawler for one domain at a time, this makes it easier to respect the crawl_delay (still need to do)
-                    let _ = database.urlqueue_push(crawled_url.as_str(), depth+1, crawler_id);
+    let dereferenced_url_object = match Url::parse(dereferenced_url) {
+        Ok(t) => t,
+        Err(_) => { 
+            trace!("Failed to convert dereferenced url to url object");
+            schedule_failure(database, scheduler, crawler_id, dereferenced_url, previous_schedule, depth);
+            return;
+        }
+    };
+
+    trace!("{}  | Finished post fetch", crawler_id);
+
+    for raw_crawled_url in &parsed_content.urls {
+        // Tries to parse a url. if it gets something like "/domains", it fails and then tries to join the path to the parent url,
+        // so it would spit out "iana.org/domains". It double fails on fragments (good thing, they are stupid anyways). Part of me 
+        // wants to make this an if statement but idiomatic code has corrupted me.
+        let crawled_url = match Url::parse(raw_crawled_url) {
+            Ok(mut t) => {
+                filter_url(&mut t);
+                t
+            },
+            Err(_t) => {
+                match dereferenced_url_object.join(raw_crawled_url) {
+                    Ok(mut t) => {
+                        filter_url(&mut t);
+                        t
+                    },
+                    Err(_t) => continue
                 }
-            } else {
-                // if the domain is different, just add the domain unowned by any crawler
-                let _ = database.urlqueue_push(convert_url_to_domain(&crawled_url).as_str(), 0, 0);
             }
+        };
+
+        match database.crawledurls_status(crawled_url.as_str()) {
+            database::UsedUrlStatus::CannotCrawlUrl => {continue;}
+            _ => {}
+        };
+
+        if crawled_url.scheme() != "https" && crawled_url.scheme() != "http" {
+            debug!("Invalid schema on {}", crawled_url.as_str());
+            continue;
         }
 
-        trace!("{}  | Finished URL parsing", crawler_id);
+        // no host, no index
+        let crawled_url_host: &str = match crawled_url.domain() {
+            Some(t) => t,
+            None => continue
+        };
 
-        match database.write_crawled_page(&parsed_content, &dereferenced_url) {
-            Ok(_) => {},
-            Err(database::Error::SQLError(Some(t))) => {
-                warn!("{}  | Couldnt write {} to db {:?}", crawler_id, dereferenced_url, t);
-                continue; 
-            },
-            Err(t) => {
-                warn!("{}  | Couldnt write {} to db {:?}", crawler_id, dereferenced_url, t);
-                continue;
+        if Some(crawled_url_host) == dereferenced_url_object.domain() {
+            // has to be nested since we dont want depth above max being put on the queue
+            if depth + 1 <= max_crawl_depth {
+                // add the url to the queue, and set the id of the crawler responsible for it. One crawler for one domain at a time, this makes it easier to respect the crawl_delay (still need to do)
+                let _ = database.urlqueue_push(crawled_url.as_str(), depth+1, crawler_id);
             }
+        } else {
+            // if the domain is different, just add the domain unowned by any crawler
+            let _ = database.urlqueue_push(convert_url_to_domain(&crawled_url).as_str(), 0, 0);
         }
+    }
 
-        trace!("{}  | Finished crawling page", crawler_id);
+    trace!("{}  | Finished URL parsing", crawler_id);
 
-        std::thread::sleep(std::time::Duration::from_secs(5));
+    match database.write_crawled_page(&parsed_content, dereferenced_url) {
+        Ok(_) => {},
+        Err(database::Error::SQLError(Some(t))) => {
+            warn!("{}  | Couldnt write {} to db {:?}", crawler_id, dereferenced_url, t);
+            schedule_failure(database, scheduler, crawler_id, dereferenced_url, previous_schedule, depth);
+            return; 
+        },
+        Err(t) => {
+            warn!("{}  | Couldnt write {} to db {:?}", crawler_id, dereferenced_url, t);
+            schedule_failure(database, scheduler, crawler_id, dereferenced_url, previous_schedule, depth);
+            return;
+        }
     }
 
-    error!("Crawler {} had no urls for 5 loops, exiting...", crawler_id);
+    // the schedule is written after the page, otherwise a failed write would be remembered as unchanged on the next crawl
+    match database.crawledurls_set(dereferenced_url, &schedule) {
+        Ok(_) => {},
+        Err(t) => warn!("{}  | Couldnt update schedule for {} {:?}", crawler_id, dereferenced_url, t)
+    }
+
+    trace!("{}  | Finished crawling page", crawler_id);
+}
+
+// links are queued as http, but a page is stored under the url the fetch ended up at, which is usually the https one.
+// Returns the schedule and the url it is stored under
+fn find_schedule(database: &mut dyn database::Database, url: &str) -> Option<(String, scheduler::CrawlSchedule)> {
+    match database.crawledurls_get(url) {
+        Some(t) => return Some((url.to_string(), t)),
+        None => {}
+    };
+
+    let mut https_url = match Url::parse(url) {
+        Ok(t) => t,
+        Err(_) => return None
+    };
+    if https_url.scheme() != "http" || https_url.set_scheme("https").is_err() {
+        return None;
+    }
+    return database.crawledurls_get(https_url.as_str()).map(|t| (https_url.to_string(), t));
+}
+
+// a fetch, parse or write that might work next time. The url gets a backed off schedule, without one it would be queued
+// again on every check and fetched again every time a page links to it
+fn schedule_failure(database: &mut dyn database::Database, scheduler: &scheduler::Scheduler, crawler_id: i32, url: &str, previous_schedule: Option<&scheduler::CrawlSchedule>, depth: i32) {
+    let schedule = scheduler.failed(previous_schedule, depth);
+    debug!("{}  | {} failed {} time(s) in a row, trying again in {}s", crawler_id, url, schedule.failure_count, scheduler.failure_interval(schedule.failure_count));
+    match database.crawledurls_set(url, &schedule) {
+        Ok(_) => {},
+        Err(t) => warn!("{}  | Couldnt update schedule for {} {:?}", crawler_id, url, t)
+    };
+}
+
+// gives up a reservation of a url we didnt crawl after all. It goes back to its schedule, or if it had none, away
+fn release(database: &mut dyn database::Database, crawler_id: i32, url: &str, previous_schedule: Option<&scheduler::CrawlSchedule>) {
+    let released = match previous_schedule {
+        Some(t) => database.crawledurls_set(url, t),
+        None => database.crawledurls_release(url)
+    };
+    match released {
+        Ok(_) => {},
+        Err(t) => warn!("{}  | Couldnt release {} {:?}", crawler_id, url, t)
+    };
 }
 
 fn filter_url(url: &mut url::Url) {
@@ -1,10 +1,13 @@
 // The parser doesnt implement an interface as it doesnt need state. Its job is to take raw bytes, and spit out some data regarding the content
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::default::Default;
 use std::ops::{Deref, DerefMut};
 
 use regex::Regex;
+use url::Url;
+use analysis::Analyzers;
+use analysis::page_text;
+pub use analysis::page_text::TextRun;
 
 use html5ever::interface::QualName;
 use html5ever::tendril::*;
@@ -14,19 +17,38 @@ use html5ever::tokenizer::{EndTag, StartTag, TagToken};
 use html5ever::tokenizer::{Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts};
 use html5ever::{LocalName, ns};
 
+use crate::charset;
+
+// how much body text is kept for language detection
+const TEXT_SAMPLE_LENGTH: usize = 2048;
+
 #[derive(Clone)]
 pub struct ParsedData {
     pub description: String,
     pub title: String,
     pub words: Vec<Word>,
+    // body text in document order with the tag it sits in, turned into words by analyze once the language is known
+    pub texts: Vec<TextRun>,
     pub urls: Vec<String>,
+    // the encoding the page was decoded from
+    pub encoding: String,
+    // the lang attribute on <html>, if there is one
+    pub html_lang: Option<String>,
+    // the start of the body text, untouched so the language detector has real words to look at
+    pub text_sample: String,
+    // BCP-47 tag, set by the crawler once it has decided what language the page is in
+    pub language: String,
 }
 
 #[derive(Clone)]
 pub struct Word {
     pub word: String,
+    // the most common way the word was written on the page, for highlighting
+    pub surface: String,
     pub parent: String,
     pub count: i32,
+    // where in the page each occurrence is, counted in tokens
+    pub positions: Vec<u32>,
 }
 
 #[derive(Debug)]
@@ -38,7 +60,6 @@ pub enum ParseHTMLError {
 struct TokenSinkState {
     pub parent: Vec<String>,
     pub parsed_data: ParsedData,
-    pub words: HashMap<(String, String), i32>,
 }
 
 struct TokenSinkWrapper {
@@ -50,6 +71,7 @@ impl TokenSink for TokenSinkWrapper {
 
     fn process_token(&self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
         let link_name = QualName::new(None, ns!(), LocalName::from("href"));
+        let lang_name = QualName::new(None, ns!(), LocalName::from("lang"));
 
         let mut binding = self.rc.borrow_mut();
         let state: &mut TokenSinkState = binding.deref_mut();
@@ -64,6 +86,12 @@ impl TokenSink for TokenSinkWrapper {
                             state.parsed_data.urls.push(attr.value.to_string());
                         }
                     }
+                } else if tag.name.to_string() == "html" {
+                    for attr in tag.attrs {
+                        if attr.name == lang_name {
+                            state.parsed_data.html_lang = Some(attr.value.to_string());
+                        }
+                    }
                 }
             }
             TagToken(tag) if tag.kind == EndTag => {
@@ -86,24 +114,18 @@ impl TokenSink for TokenSinkWrapper {
                     None => &String::from(""),
                 };
 
-                let words = tendril.deref().split(" ");
-
-                for word in words {
-                    let clean_word = clean_alphanumeric(word);
-
-                    if clean_word.len() == 0 {
-                        continue
-                    }
-
-                    match state.words.insert((clean_word.clone(), parent.clone()), 1) {
-                        Some(n) => {
-                            state
-                                .words
-                                .insert((clean_word.clone(), parent.clone()), n + 1);
-                        }
-                        None => {}
-                    }
+                // scripts and styles would only confuse the language detector. The space between runs counts towards the length
+                let sampled = state.parsed_data.text_sample.chars().count();
+                if parent != "script" && parent != "style" && sampled < TEXT_SAMPLE_LENGTH {
+                    state.parsed_data.text_sample.push(' ');
+                    state.parsed_data.text_sample.push_str(&safe_truncate(&(*tendril).to_string(), TEXT_SAMPLE_LENGTH - sampled - 1));
                 }
+
+                let run = TextRun {
+                    parent: parent.clone(),
+                    text: tendril.deref().to_string()
+                };
+                state.parsed_data.texts.push(run);
             }
 
             CharacterTokens(tendril) if state.parent.contains(&String::from("head")) => {
@@ -125,27 +147,32 @@ impl TokenSink for TokenSinkWrapper {
     }
 }
 
-pub fn parse_html(content: Vec<u8>, _url: &String) -> Result<ParsedData, ParseHTMLError> {
+pub fn parse_html(content: Vec<u8>, content_type: Option<&str>, url: &String) -> Result<ParsedData, ParseHTMLError> {
     let sink: RefCell<TokenSinkState> = RefCell::new(TokenSinkState {
         parent: vec!["".to_string()],
-        words: [].into(),
         parsed_data: ParsedData {
             description: String::from(""),
             title: String::from(""),
             words: vec![],
+            texts: vec![],
             urls: vec![],
+            encoding: String::from(""),
+            html_lang: None,
+            text_sample: String::from(""),
+            language: String::from(""),
         },
     });
 
     let mut input = BufferQueue::default();
 
-    // Convert Vec<u8> to ByteTendril and push to input
-    let tendril = ByteTendril::from_slice(&content);
-    let try_reinterpret = match tendril.try_reinterpret::<fmt::UTF8>() {
-        Ok(t) => t,
-        Err(_) => return Err(ParseHTMLError::TextDecodeFailed),
+    // the top level domain is a hint for the charset detector, .jp pages are far more likely to be shift_jis than .de ones
+    let tld = match Url::parse(url) {
+        Ok(t) => t.domain().and_then(|t| t.rsplit('.').next()).map(|t| t.to_string()),
+        Err(_) => None
     };
-    input.push_back(try_reinterpret);
+
+    let (text, encoding) = charset::decode(&content, content_type, tld.as_deref());
+    input.push_back(StrTendril::from(text));
 
     let tok = Tokenizer::new(TokenSinkWrapper { rc: sink }, TokenizerOpts::default());
     let _ = tok.feed(&mut input);
@@ -153,23 +180,28 @@ pub fn parse_html(content: Vec<u8>, _url: &String) -> Result<ParsedData, ParseHT
 
     let sink_state = tok.sink.rc.into_inner(); // Use into_inner() to take ownership
     let mut parsed_data = sink_state.parsed_data;
-
-    for ((word, parent), count) in sink_state.words {
-        let o = Word{
-            word: word,
-            parent: parent,
-            count: count
-        };
-        parsed_data.words.push(o);
-    }
+    parsed_data.encoding = encoding.name().to_string();
 
     parsed_data.description = clean_description(&parsed_data.description);
 
     return Ok(parsed_data);
 }
 
+// the terms are counted in the analysis crate, so a page reindexed from its stored text gets the same words as a crawl
+pub fn analyze(parsed_data: &mut ParsedData, analyzers: &Analyzers) {
+    parsed_data.words = page_text::analyze(&parsed_data.texts, &parsed_data.language, analyzers).into_iter().map(|t| {
+        Word {
+            word: t.term,
+            surface: t.surface,
+            parent: t.parent,
+            count: t.count,
+            positions: t.positions
+        }
+    }).collect();
+}
+
 fn clean_description(text: &str) -> String {
-    let remove_non_alphanumeric = Regex::new(r"(^ )|[^a-zA-Z\d ]|[\r\n]").expect("clean_description regex did not compile");
+    let remove_non_alphanumeric = Regex::new(r"(^ )|[^\p{L}\p{N} ]|[\r\n]").expect("clean_description regex did not compile");
     let cleaned = remove_non_alphanumeric.replace_all(&text, "").to_string();
     return cleaned;
 }
@@ -178,10 +210,18 @@ pub fn safe_truncate(string: &String, count: usize) -> String {
     return string.chars().take(count).collect();
 }
 
-fn clean_alphanumeric(text: &str) -> String {
-    let remove_non_alphanumeric = Regex::new(r"[^a-zA-Z\d]").expect("clean_alphanumeric regex did not compile");
-    let cleaned = remove_non_alphanumeric
-        .replace_all(&text, "")
-        .to_lowercase();
-    return cleaned;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_sample_stops_at_its_length() {
+        // multibyte characters, so a cut by bytes would land inside one
+        let run = "é".repeat(1500);
+        let html = format!("<html><body><p>{0}</p><p>{0}</p><p>{0}</p></body></html>", run);
+        let parsed = parse_html(html.into_bytes(), Some("text/html; charset=utf-8"), &"https://example.com/".to_string()).unwrap();
+
+        assert_eq!(parsed.text_sample.chars().count(), TEXT_SAMPLE_LENGTH);
+        assert_eq!(parsed.text_sample, format!(" {} {}", run, "é".repeat(TEXT_SAMPLE_LENGTH - 1502)));
+    }
 }
@@ -1,5 +1,5 @@
 // This is a high level request handler, whose job is to make http requests and return the dereferenced url and the byte vector content
-// It is expected to manage robots.txt, and dereference 3XX urls
+// It is expected to manage robots.txt, and dereference 3XX urls. Validators from a previous crawl are passed through so unchanged pages come back as 304
 
 use crate::robots_txt;
 use crate::http_request;
@@ -9,7 +9,7 @@ use url::Url;
 use log::{warn, info};
 
 pub trait RequestHandler<'a, 'b> {
-    fn fetch(&mut self, url: &str) -> Result<(Vec<u8>, String), RequestHandlerError>;
+    fn fetch(&mut self, url: &str, validators: Option<&http_request::CacheValidators>) -> Result<http_request::HTTPResponse, RequestHandlerError>;
 }
 
 #[allow(dead_code)]
@@ -20,6 +20,16 @@ pub enum RequestHandlerError {
     HTTPRequestError(http_request::HTTPRequestError)
 }
 
+impl RequestHandlerError {
+    // whether the page is gone for good, being disallowed by robots.txt doesnt mean it stopped existing
+    pub fn is_permanent(&self) -> bool {
+        return match self {
+            RequestHandlerError::HTTPRequestError(t) => t.is_permanent(),
+            _ => false
+        };
+    }
+}
+
 pub struct SimpleRequestHandler<'a, 'b> {
     robotstxt: &'a mut dyn robots_txt::RobotsTXT,
     http_request: &'b http_request::HTTPRequest,
@@ -27,7 +37,7 @@ pub struct SimpleRequestHandler<'a, 'b> {
 }
 
 impl<'a, 'b> RequestHandler<'a, 'b> for SimpleRequestHandler<'a, 'b> {
-    fn fetch(&mut self, url: &str) -> Result<(Vec<u8>, String), RequestHandlerError> {
+    fn fetch(&mut self, url: &str, validators: Option<&http_request::CacheValidators>) -> Result<http_request::HTTPResponse, RequestHandlerError> {
         let mut url_object = match Url::parse(url) {
             Ok(t) => t,
             Err(_) => return Err(RequestHandlerError::BadURL)
@@ -55,20 +65,11 @@ impl<'a, 'b> RequestHandler<'a, 'b> for SimpleRequestHandler<'a, 'b> {
             return Err(RequestHandlerError::DisallowedByRobotsTxt)
         }
 
-        let page_content: Vec<u8>;
-        let new_url: String;
-        
-        match self.http_request.request(url_object.as_str(), None) {
-            Ok(t) => {
-                page_content = t.0;
-                new_url = t.1;
-            }
+        match self.http_request.request(url_object.as_str(), validators, None) {
+            Ok(t) => return Ok(t),
             Err(t) => return Err(RequestHandlerError::HTTPRequestError(t))
         }
 
-
-        return Ok((page_content, new_url))
-
     }
 }
 
@@ -55,8 +55,8 @@ impl RobotsTXTCrate {
 
         // println!("robotstxt url: {}", robots_path.as_str());
         
-        let robots_bytes: Vec<u8> = match self.request_object.request(robots_path.as_str(), None) {
-            Ok(t) => t.0,
+        let robots_bytes: Vec<u8> = match self.request_object.request(robots_path.as_str(), None, None) {
+            Ok(t) => t.content,
             Err(_) => vec![]
         };
 
@@ -72,7 +72,7 @@ impl RobotsTXTCrate {
     fn test_init(content: &str) -> Self {
         return RobotsTXTCrate {
             content: content.to_string(),
-            request_object: http_request::HTTPRequest::new("")
+            request_object: http_request::HTTPRequest::new("", 15 * 1024 * 1024, false, None)
         }
     }
     
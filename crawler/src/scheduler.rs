@@ -0,0 +1,294 @@
+// Decides when a url should be crawled again. Every fetch stores a hash of the page content, and the revisit interval
+// shrinks when the page changed since the last crawl and grows when it didnt, clamped between the configured bounds.
+// Pages that change often (news) settle near the minimum interval, pages that never change (docs) settle near the maximum
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::config::RecrawlConfig;
+use crate::http_request::CacheValidators;
+use crate::parser;
+
+#[derive(Clone, Debug)]
+pub struct CrawlSchedule {
+    pub depth: i32,
+    pub content_hash: i64,
+    pub last_crawled_at: i64,
+    pub crawl_again_at: i64,
+    pub recrawl_interval: i64,
+    pub fetch_count: i32,
+    pub change_count: i32,
+    // 404 or 410s in a row since the page was last fetched
+    pub gone_count: i32,
+    // fetches in a row that got no answer we could use, timeouts, 5xx, robots.txt or a page we couldnt parse or write
+    pub failure_count: i32,
+    pub validators: CacheValidators
+}
+
+#[derive(Clone)]
+pub struct Scheduler {
+    min_interval: i64,
+    max_interval: i64,
+    default_interval: i64,
+    gone_confirmations: i32
+}
+
+impl Scheduler {
+    pub fn new(conf: &RecrawlConfig) -> Self {
+        return Scheduler {
+            min_interval: conf.min_interval,
+            max_interval: conf.max_interval,
+            default_interval: conf.default_interval.clamp(conf.min_interval, conf.max_interval),
+            gone_confirmations: conf.gone_confirmations.max(1)
+        }
+    }
+
+    // builds the schedule for a url that was just fetched. Returns the new schedule and whether the content changed since the last fetch
+    pub fn next(&self, previous: Option<&CrawlSchedule>, depth: i32, content_hash: i64, validators: CacheValidators) -> (CrawlSchedule, bool) {
+        let now = now();
+
+        // a url whose fetches only ever failed has nothing to compare against yet
+        let previous = match previous {
+            Some(t) if t.fetch_count > 0 => t,
+            _ => {
+                return (CrawlSchedule {
+                    depth: depth,
+                    content_hash: content_hash,
+                    last_crawled_at: now,
+                    crawl_again_at: now + self.default_interval,
+                    recrawl_interval: self.default_interval,
+                    fetch_count: 1,
+                    change_count: 0,
+                    gone_count: 0,
+                    failure_count: 0,
+                    validators: validators
+                }, true)
+            }
+        };
+
+        let changed = previous.content_hash != content_hash;
+        let interval = self.next_interval(previous.recrawl_interval, changed);
+
+        return (CrawlSchedule {
+            // keep the shallowest depth we have seen the url at, so a recrawl doesnt stop following links early
+            depth: depth.min(previous.depth),
+            content_hash: content_hash,
+            last_crawled_at: now,
+            crawl_again_at: now + interval,
+            recrawl_interval: interval,
+            fetch_count: previous.fetch_count + 1,
+            change_count: previous.change_count + changed as i32,
+            gone_count: 0,
+            failure_count: 0,
+            validators: validators
+        }, changed)
+    }
+
+    // a page that was there before answered 404 or 410. It is checked again soon in case it was a mistake, and once it
+    // has been gone gone_confirmations times in a row it is tombstoned and only checked at the slowest rate, in case it comes back.
+    // Returns the new schedule and whether the page should be tombstoned now
+    pub fn gone(&self, previous: &CrawlSchedule) -> (CrawlSchedule, bool) {
+        let now = now();
+        let gone_count = previous.gone_count + 1;
+        let tombstoned = gone_count >= self.gone_confirmations;
+
+        let mut schedule = previous.clone();
+        schedule.last_crawled_at = now;
+        schedule.crawl_again_at = now + if tombstoned { self.max_interval } else { self.min_interval };
+        schedule.fetch_count = previous.fetch_count + 1;
+        schedule.gone_count = gone_count;
+        schedule.failure_count = 0;
+        // the tombstone deletes what we kept of the page, so if it comes back it has to count as changed and be written again.
+        // Without validators the fetch cant get a 304 either
+        if tombstoned {
+            schedule.content_hash = 0;
+            schedule.validators = CacheValidators::default();
+        }
+        // every confirmation past the limit asks again, tombstoning is idempotent and a tombstone that failed to write gets another go
+        return (schedule, tombstoned);
+    }
+
+    // the fetch failed in a way that might work next time. It is tried again after min_interval, doubled for every failure
+    // in a row up to max_interval, so a broken page isnt queued again on every check. Everything we know about the page
+    // stays as it was, including the content hash, so the next fetch that works still compares against the last good one
+    pub fn failed(&self, previous: Option<&CrawlSchedule>, depth: i32) -> CrawlSchedule {
+        let now = now();
+
+        let mut schedule = match previous {
+            Some(t) => t.clone(),
+            None => CrawlSchedule {
+                depth: depth,
+                content_hash: 0,
+                last_crawled_at: 0,
+                crawl_again_at: 0,
+                recrawl_interval: self.default_interval,
+                fetch_count: 0,
+                change_count: 0,
+                gone_count: 0,
+                failure_count: 0,
+                validators: CacheValidators::default()
+            }
+        };
+        schedule.depth = depth.min(schedule.depth);
+        schedule.failure_count += 1;
+        schedule.crawl_again_at = now + self.failure_interval(schedule.failure_count);
+        return schedule;
+    }
+
+    pub fn failure_interval(&self, failure_count: i32) -> i64 {
+        let doublings = (failure_count - 1).clamp(0, 32) as u32;
+        return self.min_interval.saturating_mul(1 << doublings).min(self.max_interval);
+    }
+
+    // a 304 means the page is unchanged without us having seen the content
+    pub fn not_modified(&self, previous: &CrawlSchedule, depth: i32, validators: CacheValidators) -> CrawlSchedule {
+        return self.next(Some(previous), depth, previous.content_hash, validators).0;
+    }
+
+    // halve the interval when the page changed, grow it by half when it didnt
+    pub fn next_interval(&self, current: i64, changed: bool) -> i64 {
+        let next = if changed {
+            current / 2
+        } else {
+            current + current / 2
+        };
+        return next.clamp(self.min_interval, self.max_interval);
+    }
+}
+
+// hashes the parsed content rather than the raw bytes, so things like csrf tokens and timestamps in scripts dont count as a change.
+// The words come out of a hashmap in the parser, so they are sorted to keep the hash stable between crawls
+pub fn content_hash(page: &parser::ParsedData) -> i64 {
+    let mut words: BTreeMap<(&str, &str), i32> = BTreeMap::new();
+    for word in page.words.iter() {
+        words.insert((&word.word, &word.parent), word.count);
+    }
+
+    let mut content: Vec<u8> = vec![];
+    content.extend_from_slice(page.title.as_bytes());
+    content.push(0);
+    content.extend_from_slice(page.description.as_bytes());
+    for ((word, parent), count) in words {
+        content.push(0);
+        content.extend_from_slice(word.as_bytes());
+        content.push(0);
+        content.extend_from_slice(parent.as_bytes());
+        content.extend_from_slice(&count.to_le_bytes());
+    }
+
+    return xxh3_64(&content) as i64;
+}
+
+pub fn now() -> i64 {
+    return SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_scheduler() -> Scheduler {
+        return Scheduler::new(&RecrawlConfig {
+            min_interval: 3600,
+            max_interval: 30 * 86400,
+            default_interval: 7 * 86400,
+            check_interval: 60,
+            gone_confirmations: 3
+        })
+    }
+
+    #[test]
+    fn scheduler_interval_bounds() {
+        let scheduler = test_scheduler();
+
+        assert_eq!(scheduler.next_interval(7200, true), 3600);
+        assert_eq!(scheduler.next_interval(3600, true), 3600);
+        assert_eq!(scheduler.next_interval(3600, false), 5400);
+        assert_eq!(scheduler.next_interval(29 * 86400, false), 30 * 86400);
+    }
+
+    #[test]
+    fn scheduler_tracks_changes() {
+        let scheduler = test_scheduler();
+
+        let (first, changed) = scheduler.next(None, 2, 10, CacheValidators::default());
+        assert!(changed);
+        assert_eq!(first.recrawl_interval, 7 * 86400);
+        assert_eq!(first.fetch_count, 1);
+
+        let (second, changed) = scheduler.next(Some(&first), 3, 10, CacheValidators::default());
+        assert!(!changed);
+        assert_eq!(second.depth, 2);
+        assert_eq!(second.recrawl_interval, 7 * 86400 + 7 * 86400 / 2);
+        assert_eq!(second.change_count, 0);
+
+        let (third, changed) = scheduler.next(Some(&second), 1, 11, CacheValidators::default());
+        assert!(changed);
+        assert_eq!(third.depth, 1);
+        assert_eq!(third.fetch_count, 3);
+        assert_eq!(third.change_count, 1);
+    }
+
+    #[test]
+    fn scheduler_backs_off_failures() {
+        let scheduler = test_scheduler();
+
+        assert_eq!(scheduler.failure_interval(1), 3600);
+        assert_eq!(scheduler.failure_interval(2), 7200);
+        assert_eq!(scheduler.failure_interval(4), 8 * 3600);
+        assert_eq!(scheduler.failure_interval(100), 30 * 86400);
+
+        // a url that never worked gets a schedule too, so links to it dont fetch it again
+        let started = now();
+        let first = scheduler.failed(None, 2);
+        assert_eq!(first.failure_count, 1);
+        assert_eq!(first.fetch_count, 0);
+        assert!((3600..=3601).contains(&(first.crawl_again_at - started)));
+        let second = scheduler.failed(Some(&first), 3);
+        assert_eq!(second.depth, 2);
+        assert!((7200..=7201).contains(&(second.crawl_again_at - started)));
+
+        let (fetched, changed) = scheduler.next(Some(&second), 2, 10, CacheValidators::default());
+        assert!(changed);
+        assert_eq!(fetched.fetch_count, 1);
+        assert_eq!(fetched.failure_count, 0);
+
+        // a failure keeps the last good hash, so the page still counts as changed once it can be written
+        let failed = scheduler.failed(Some(&fetched), 2);
+        assert_eq!(failed.content_hash, 10);
+        assert_eq!(failed.recrawl_interval, fetched.recrawl_interval);
+        let (_, changed) = scheduler.next(Some(&failed), 2, 11, CacheValidators::default());
+        assert!(changed);
+    }
+
+    #[test]
+    fn scheduler_tombstones_after_confirmations() {
+        let scheduler = test_scheduler();
+        let validators = CacheValidators { etag: Some("\"v1\"".to_string()), last_modified: None };
+        let (first, _) = scheduler.next(None, 1, 10, validators.clone());
+
+        let (second, tombstoned) = scheduler.gone(&first);
+        assert!(!tombstoned);
+        assert_eq!(second.crawl_again_at - second.last_crawled_at, 3600);
+        // still indexed until the tombstone, so an unchanged page that answers again needs no rewrite
+        assert_eq!(second.validators, validators);
+        let (_, changed) = scheduler.next(Some(&second), 1, 10, validators.clone());
+        assert!(!changed);
+        let (third, tombstoned) = scheduler.gone(&second);
+        assert!(!tombstoned);
+        let (fourth, tombstoned) = scheduler.gone(&third);
+        assert!(tombstoned);
+        assert_eq!(fourth.crawl_again_at - fourth.last_crawled_at, 30 * 86400);
+        let (_, tombstoned) = scheduler.gone(&fourth);
+        assert!(tombstoned);
+
+        assert_eq!(fourth.validators, CacheValidators::default());
+
+        // the page came back as it was, and has to be written again since the tombstone deleted it
+        let (back, changed) = scheduler.next(Some(&fourth), 1, 10, validators);
+        assert_eq!(back.gone_count, 0);
+        assert!(changed);
+    }
+}
@@ -0,0 +1,290 @@
+// Writes every fetch to WARC/1.1 files, the format the Internet Archive and Common Crawl use, so raw responses can be
+// looked at when extraction goes wrong, parsed again, or shared. Each fetch is a request, a response and a metadata record,
+// each record its own gzip member so tools can seek to one without reading the whole file. Files are started in warc.dir
+// and a new one is begun once the current one passes warc.rotate_size, always between two fetches
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use analysis::dates;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use reqwest::blocking::{Request, Response};
+use uuid::Uuid;
+
+use crate::config::WarcConfig;
+
+#[derive(Clone)]
+pub struct WarcWriter {
+    dir: PathBuf,
+    rotate_size: u64,
+    user_agent: String,
+    state: Arc<Mutex<WarcState>>
+}
+
+struct WarcState {
+    file: Option<File>,
+    // compressed bytes in the current file
+    written: u64,
+    // files started by this process, part of the name so two files started in the same second dont collide
+    files: u64,
+    // every record points back at the warcinfo record at the start of its file
+    warcinfo_id: String
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum WarcError {
+    IOError(std::io::Error)
+}
+
+impl From<std::io::Error> for WarcError {
+    fn from(error: std::io::Error) -> Self {
+        return WarcError::IOError(error);
+    }
+}
+
+// one request and the head of its response, the body is added once the crawler knows how much of it it read
+pub struct Exchange {
+    url: String,
+    request: Vec<u8>,
+    response_head: Vec<u8>,
+    date: SystemTime,
+    started: Instant
+}
+
+impl Exchange {
+    // reqwest only gives us the parsed request, so the http message is put back together from it. The client adds
+    // the user agent and accept headers on its own, so they are added here too
+    pub fn new(request: &Request, user_agent: &str) -> Self {
+        let url = request.url();
+        let mut target = url.path().to_string();
+        match url.query() {
+            Some(t) => target = format!("{}?{}", target, t),
+            None => {}
+        }
+
+        let mut message = format!("{} {} HTTP/1.1\r\n", request.method(), target).into_bytes();
+        match url.port() {
+            Some(t) => write_header(&mut message, "host", format!("{}:{}", url.host_str().unwrap_or(""), t).as_bytes()),
+            None => write_header(&mut message, "host", url.host_str().unwrap_or("").as_bytes())
+        }
+        write_header(&mut message, "user-agent", user_agent.as_bytes());
+        write_header(&mut message, "accept", b"*/*");
+        for (name, value) in request.headers() {
+            write_header(&mut message, name.as_str(), value.as_bytes());
+        }
+        message.extend_from_slice(b"\r\n");
+
+        return Exchange {
+            url: url.to_string(),
+            request: message,
+            response_head: vec![],
+            date: SystemTime::now(),
+            started: Instant::now()
+        };
+    }
+
+    // the body is stored without its chunked transfer encoding, so the header is renamed the way common crawl does
+    // it, otherwise readers would try to dechunk it again
+    pub fn with_response(self, response: &Response) -> Self {
+        let mut head = format!("{:?} {}\r\n", response.version(), response.status()).into_bytes();
+        for (name, value) in response.headers() {
+            match name.as_str() {
+                "transfer-encoding" => write_header(&mut head, "x-crawler-transfer-encoding", value.as_bytes()),
+                t => write_header(&mut head, t, value.as_bytes())
+            }
+        }
+        head.extend_from_slice(b"\r\n");
+
+        return Exchange {
+            response_head: head,
+            ..self
+        };
+    }
+
+    pub fn url(&self) -> &str {
+        return &self.url;
+    }
+}
+
+impl WarcWriter {
+    pub fn new(conf: &WarcConfig, user_agent: &str) -> Result<Self, WarcError> {
+        fs::create_dir_all(&conf.dir)?;
+
+        return Ok(WarcWriter {
+            dir: PathBuf::from(&conf.dir),
+            rotate_size: conf.rotate_size,
+            user_agent: user_agent.to_string(),
+            state: Arc::new(Mutex::new(WarcState {
+                file: None,
+                written: 0,
+                files: 0,
+                warcinfo_id: String::new()
+            }))
+        });
+    }
+
+    // truncated is the WARC-Truncated reason when body isnt the whole body, "length" when it was cut at max_page_size and
+    // "unspecified" when the crawler never downloaded it
+    pub fn write_exchange(&self, exchange: &Exchange, body: &[u8], truncated: Option<&str>, redirects: i32) -> Result<(), WarcError> {
+        let date = warc_date(exchange.date);
+        let response_id = record_id();
+
+        let mut response = exchange.response_head.clone();
+        response.extend_from_slice(body);
+
+        let mut response_headers = vec![
+            ("WARC-Type", String::from("response")),
+            ("WARC-Record-ID", response_id.clone()),
+            ("WARC-Date", date.clone()),
+            ("WARC-Target-URI", exchange.url.clone()),
+            ("Content-Type", String::from("application/http;msgtype=response"))
+        ];
+        match truncated {
+            Some(t) => response_headers.push(("WARC-Truncated", t.to_string())),
+            None => {}
+        }
+
+        let request_headers = vec![
+            ("WARC-Type", String::from("request")),
+            ("WARC-Record-ID", record_id()),
+            ("WARC-Date", date.clone()),
+            ("WARC-Target-URI", exchange.url.clone()),
+            ("WARC-Concurrent-To", response_id.clone()),
+            ("Content-Type", String::from("application/http;msgtype=request"))
+        ];
+
+        let metadata = format!("fetchTimeMs: {}\r\nredirects: {}\r\n", exchange.started.elapsed().as_millis(), redirects);
+        let metadata_headers = vec![
+            ("WARC-Type", String::from("metadata")),
+            ("WARC-Record-ID", record_id()),
+            ("WARC-Date", date),
+            ("WARC-Target-URI", exchange.url.clone()),
+            ("WARC-Concurrent-To", response_id),
+            ("Content-Type", String::from("application/warc-fields"))
+        ];
+
+        // the three records go in together so a fetch is never split across two files
+        let mut state = self.state.lock().unwrap();
+        if state.file.is_none() || state.written >= self.rotate_size {
+            self.rotate(&mut state)?;
+        }
+        let warcinfo_id = state.warcinfo_id.clone();
+        for (headers, block) in [(request_headers, exchange.request.as_slice()), (response_headers, response.as_slice()), (metadata_headers, metadata.as_bytes())] {
+            let mut headers = headers;
+            headers.push(("WARC-Warcinfo-ID", warcinfo_id.clone()));
+            Self::write_record(&mut state, &headers, block)?;
+        }
+        return Ok(());
+    }
+
+    fn rotate(&self, state: &mut WarcState) -> Result<(), WarcError> {
+        let now = SystemTime::now();
+        let timestamp: String = warc_date(now).chars().filter(|t| t.is_ascii_digit()).collect();
+        let name = format!("crawler-{}-{}-{:05}.warc.gz", timestamp, std::process::id(), state.files);
+
+        state.file = Some(OpenOptions::new().write(true).create_new(true).open(self.dir.join(&name))?);
+        state.written = 0;
+        state.files += 1;
+        state.warcinfo_id = record_id();
+
+        let info = format!(
+            "software: crawler/{}\r\nformat: WARC File Format 1.1\r\nconformsTo: http://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/\r\nhttp-header-user-agent: {}\r\nrobots: obey\r\n",
+            env!("CARGO_PKG_VERSION"), self.user_agent
+        );
+        let headers = vec![
+            ("WARC-Type", String::from("warcinfo")),
+            ("WARC-Record-ID", state.warcinfo_id.clone()),
+            ("WARC-Date", warc_date(now)),
+            ("WARC-Filename", name),
+            ("Content-Type", String::from("application/warc-fields"))
+        ];
+        return Self::write_record(state, &headers, info.as_bytes());
+    }
+
+    fn write_record(state: &mut WarcState, headers: &[(&str, String)], block: &[u8]) -> Result<(), WarcError> {
+        let mut record = b"WARC/1.1\r\n".to_vec();
+        for (name, value) in headers {
+            write_header(&mut record, name, value.as_bytes());
+        }
+        write_header(&mut record, "Content-Length", block.len().to_string().as_bytes());
+        record.extend_from_slice(b"\r\n");
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&record)?;
+        let compressed = encoder.finish()?;
+
+        match state.file.as_mut() {
+            Some(t) => t.write_all(&compressed)?,
+            None => {}
+        }
+        state.written += compressed.len() as u64;
+        return Ok(());
+    }
+}
+
+fn write_header(out: &mut Vec<u8>, name: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn record_id() -> String {
+    return format!("<urn:uuid:{}>", Uuid::new_v4());
+}
+
+// yyyy-mm-ddThh:mm:ssZ in utc
+fn warc_date(time: SystemTime) -> String {
+    return dates::format_timestamp(time.duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Duration;
+    use flate2::read::MultiGzDecoder;
+
+    #[test]
+    fn warc_records_and_rotation() {
+        assert_eq!(warc_date(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(warc_date(SystemTime::UNIX_EPOCH + Duration::from_secs(951868800 + 3723)), "2000-03-01T01:02:03Z");
+
+        let dir = std::env::temp_dir().join(format!("crawler-warc-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let writer = WarcWriter::new(&WarcConfig { enabled: true, dir: dir.to_string_lossy().to_string(), rotate_size: 1 }, "test-agent").unwrap();
+
+        let request = reqwest::blocking::Client::new().get("http://example.com:8080/a?b=c").build().unwrap();
+        let exchange = Exchange::new(&request, "test-agent");
+        writer.write_exchange(&exchange, b"<html></html>", None, 0).unwrap();
+        writer.write_exchange(&exchange, b"", Some("unspecified"), 1).unwrap();
+
+        // rotate_size 1 starts a new file for every fetch
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|t| t.unwrap().path()).collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+
+        let mut warc = String::new();
+        MultiGzDecoder::new(File::open(&files[0]).unwrap()).read_to_string(&mut warc).unwrap();
+        assert!(warc.starts_with("WARC/1.1\r\nWARC-Type: warcinfo\r\n"));
+        assert_eq!(warc.matches("WARC/1.1\r\n").count(), 4);
+        assert!(warc.contains("GET /a?b=c HTTP/1.1\r\nhost: example.com:8080\r\nuser-agent: test-agent\r\n"));
+        assert!(warc.contains("WARC-Target-URI: http://example.com:8080/a?b=c\r\n"));
+        assert!(warc.contains("\r\n\r\n<html></html>\r\n\r\n"));
+        assert!(!warc.contains("WARC-Truncated"));
+
+        let mut warc = String::new();
+        MultiGzDecoder::new(File::open(&files[1]).unwrap()).read_to_string(&mut warc).unwrap();
+        assert!(warc.contains("WARC-Truncated: unspecified\r\n"));
+        assert!(warc.contains("redirects: 1\r\n"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
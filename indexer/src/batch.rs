@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+
+use crate::database;
+use crate::statistics::{PageStatistics, StatisticsDeltas};
+
+// the rows of several indexed pages, written to the database together in one transaction
+pub struct IndexBatch {
+    pub doc_ids: Vec<i64>,
+    // (doc_id, title, description, language, fingerprint)
+    pub pages: Vec<(i64, String, String, String, i64)>,
+    // (doc_id, stem, surface form, weight, encoded positions)
+    pub words: Vec<(i64, String, String, u64, Vec<u8>)>,
+    // (folded surface form, stem)
+    pub term_forms: HashSet<(String, String)>,
+    pub statistics: Vec<(i64, PageStatistics)>
+}
+
+impl IndexBatch {
+    pub fn new() -> Self {
+        return IndexBatch {
+            doc_ids: vec![],
+            pages: vec![],
+            words: vec![],
+            term_forms: [].into(),
+            statistics: vec![]
+        };
+    }
+
+    // everything in the batch is written in one transaction, a failure part way rolls it all back
+    pub fn consume_into_db(self: &mut Self, db: &mut dyn database::Database) -> Result<(), database::Error> {
+        match db.begin() {
+            Ok(_) => {}
+            Err(t) => return Err(t)
+        };
+        match self.write_into_db(db) {
+            Ok(_) => return db.commit(),
+            Err(t) => {
+                let _ = db.rollback();
+                return Err(t);
+            }
+        };
+    }
+
+    fn write_into_db(self: &mut Self, db: &mut dyn database::Database) -> Result<(), database::Error> {
+        match db.write_indexed_pages(&mut self.pages.drain(..)) {
+            Ok(_) => {}
+            Err(t) => return Err(t)
+        };
+        match db.write_term_forms(&mut self.term_forms.drain()) {
+            Ok(_) => {}
+            Err(t) => return Err(t)
+        };
+        // the global statistics move by the difference to what each page had before
+        let mut deltas = StatisticsDeltas::new();
+        match db.write_statistics(&self.statistics, &mut deltas) {
+            Ok(_) => {}
+            Err(t) => return Err(t)
+        };
+        match db.write_indexed_words(&self.doc_ids, &mut self.words.drain(..)) {
+            Ok(_) => {}
+            Err(t) => return Err(t)
+        };
+        // last, so the rows every indexer shares are held as briefly as possible
+        match db.write_statistics_deltas(&deltas) {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(t)
+        };
+    }
+}
@@ -1,10 +1,14 @@
+use std::collections::HashMap;
+
 use serde::{Serialize, Deserialize};
 use log::LevelFilter;
+use analysis::AnalysisConfig;
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub indexer: IndexerConfig,
-    pub database: PostgresDBInfo
+    pub database: PostgresDBInfo,
+    pub analysis: AnalysisConfig
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,8 +21,51 @@ pub struct PostgresDBInfo {
 
 #[derive(Serialize, Deserialize)]
 pub struct IndexerConfig {
+    // seconds to wait for a notification from the crawler before checking for pages anyway
     pub time_between_indexes: u64,
-    pub log: String
+    pub log: String,
+    // seconds before a page claimed by an indexer that never finished it can be claimed again
+    pub claim_timeout: u64,
+    pub retry: RetryConfig,
+    // pages claimed and written to the database together
+    pub batch_size: usize,
+    // threads weighing the pages of a batch, 0 uses every core
+    pub workers: usize,
+    pub weights: WeightConfig,
+    pub segments: SegmentConfig
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    // a page that failed this many times stays in failed_index until it is retried or purged by hand
+    pub max_attempts: i32,
+    // seconds before the first retry, doubled after every failure
+    pub backoff: u64,
+    pub max_backoff: u64
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WeightConfig {
+    // multiplier for a word per html tag it appears in, "default" for every tag that isnt listed
+    pub fields: HashMap<String, u64>,
+    // terms with a total weight below this are dropped from the page
+    pub min_weight: u64,
+    // only the heaviest terms of a page are kept, 0 keeps them all
+    pub max_terms_per_page: usize
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SegmentConfig {
+    // keep the words of the index in on-disk segments instead of indexedwords, the backend asks for them at listen
+    pub enabled: bool,
+    pub dir: String,
+    pub listen: String,
+    // pages kept in memory before they are written out as a segment, every batch is written out before it is acked anyway
+    pub flush_documents: usize,
+    // segments are merged into one once there are this many
+    pub merge_factor: usize,
+    // seconds between checks for a merge
+    pub merge_interval: u64
 }
 
 impl Config {
@@ -0,0 +1,88 @@
+use crate::config::WeightConfig;
+use crate::database;
+
+// a row whose value was added to instead of replaced, from before writes were idempotent
+pub struct InflatedRow {
+    // indexedwords or crawledwords
+    pub table: &'static str,
+    pub doc_id: i64,
+    pub url: String,
+    pub word: String,
+    pub found: i64,
+    pub expected: i64
+}
+
+// indexer check            lists indexed weights above what the term frequencies add up to, and crawled counts above
+//                          the number of positions the word is at
+// indexer check repair     sets them back to what they should be
+pub fn run_command(args: &[String], db: &mut dyn database::Database, weights: &WeightConfig) -> Result<(), String> {
+    match args.first().map(|t| t.as_str()) {
+        None => {
+            let rows = match db.inflated_rows(weights) {
+                Ok(t) => t,
+                Err(t) => return Err(format!("{:?}", t))
+            };
+            for row in rows.iter() {
+                println!("{}\t{}\t{}\t{}\tfound {}\texpected {}", row.table, row.doc_id, row.url, row.word, row.found, row.expected);
+            }
+            println!("{} inflated row(s)", rows.len());
+            return Ok(());
+        },
+        Some("repair") => match db.repair_inflated_rows(weights) {
+            Ok(t) => {
+                println!("{} row(s) repaired", t);
+                return Ok(());
+            },
+            Err(t) => return Err(format!("{:?}", t))
+        },
+        _ => return Err(String::from("usage: indexer check [repair]"))
+    };
+}
+
+// (fields, multipliers, default multiplier) the way the database takes them. The weight of a term is the sum of its
+// frequency in each field times that fields multiplier, as from_crawled_page weighs it
+pub fn multipliers(weights: &WeightConfig) -> (Vec<String>, Vec<i64>, i64) {
+    let default = match weights.fields.get("default") {
+        Some(t) => *t as i64,
+        None => 1
+    };
+    let (fields, multipliers) = weights.fields.iter().map(|(field, multiplier)| (field.clone(), *multiplier as i64)).unzip();
+    return (fields, multipliers, default);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn multiplier_of(field: &str, fields: &[String], values: &[i64], default: i64) -> i64 {
+        return match fields.iter().position(|t| t == field) {
+            Some(t) => values[t],
+            None => default
+        };
+    }
+
+    #[test]
+    fn consistency_multipliers() {
+        let mut weights = WeightConfig {
+            fields: HashMap::from([("title".to_string(), 5), ("h1".to_string(), 3), ("default".to_string(), 2)]),
+            min_weight: 0,
+            max_terms_per_page: 0
+        };
+        let (fields, values, default) = multipliers(&weights);
+        assert_eq!(fields.len(), values.len());
+        assert_eq!(default, 2);
+        assert_eq!(multiplier_of("title", &fields, &values, default), 5);
+        assert_eq!(multiplier_of("h1", &fields, &values, default), 3);
+        // a tag that isnt listed is weighed with the default, inflated_rows_found_and_repaired checks the sql does the same with $3
+        assert_eq!(multiplier_of("p", &fields, &values, default), 2);
+
+        // without a default every other tag counts once
+        weights.fields.remove("default");
+        let (fields, values, default) = multipliers(&weights);
+        assert_eq!(default, 1);
+        assert_eq!(multiplier_of("p", &fields, &values, default), 1);
+        assert_eq!(multiplier_of("title", &fields, &values, default), 5);
+    }
+}
@@ -1,16 +1,23 @@
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CrawledPage {
+    pub doc_id: i64,
+    // when this indexer claimed the page, in unix milliseconds. Acking only removes the page if this still matches
+    pub claimed_at: i64,
     pub url: String,
     pub description: String,
     pub title: String,
+    // BCP-47 tag the crawler detected, "und" when it couldnt tell
+    pub language: String,
     pub words: Vec<Word>
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Word {
     pub word: String,
+    pub surface: String,
     pub parent: String,
-    pub count: i32
+    pub count: i32,
+    pub positions: Vec<u32>
 }
 
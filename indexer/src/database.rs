@@ -1,138 +1,1026 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info, warn};
+
 use postgres::{Client, NoTls, error::SqlState};
-use crate::config::PostgresDBInfo;
+use postgres::fallible_iterator::FallibleIterator;
+use crate::config::{PostgresDBInfo, RetryConfig, WeightConfig};
+use crate::consistency::{self, InflatedRow};
 use crate::crawled_page;
+use crate::failed_index::{self, FailedPage};
+use crate::reindex::{ReindexFilter, StoredPage};
+use crate::statistics::{PageStatistics, StatisticsDeltas};
+
+// the crawler notifies on this channel with the doc_id of every page it commits
+pub const CRAWLED_PAGES_CHANNEL: &str = "crawled_pages";
 
 pub trait Database {
-    fn get_crawled_page(self: &mut Self) -> Option<crawled_page::CrawledPage>;
-    fn crawled_page_len(self: &mut Self) -> u32;
-    fn write_indexed_page(self: &mut Self, url: &str, title: &str, desc: &str) -> Result<(), Error>;
-    fn write_indexed_words(self: &mut Self, url: &str, words: &mut dyn Iterator<Item = (String, u64)>) -> Result<(), Error>;
+    // blocks until the crawler commits a page or timeout runs out. True when there might be pages to index,
+    // which is also the case after a reconnect, since notifications sent while disconnected are lost
+    fn wait_for_pages(self: &mut Self, timeout: Duration) -> bool;
+    // marks up to limit pages as taken so no other indexer gets them. A claim older than claim_timeout seconds is assumed
+    // to belong to an indexer that died, and the page can be claimed again
+    fn claim_crawled_pages(self: &mut Self, claim_timeout: u64, limit: i64) -> Vec<crawled_page::CrawledPage>;
+    // removes pages from crawleddata once they are indexed, given as (doc_id, claimed_at). A page the crawler wrote a newer
+    // version of since the claim is left alone
+    fn ack_crawled_pages(self: &mut Self, pages: &mut dyn Iterator<Item = (i64, i64)>) -> Result<(), Error>;
+    // records why a page failed in failed_index and releases the claim, it is claimed again once its backoff is over
+    fn fail_crawled_page(self: &mut Self, page: &crawled_page::CrawledPage, error: &str, retry: &RetryConfig) -> Result<(), Error>;
+    fn failed_pages(self: &mut Self) -> Result<Vec<FailedPage>, Error>;
+    // every failed page when doc_id is None. Returns how many pages were changed
+    fn retry_failed_pages(self: &mut Self, doc_id: Option<i64>) -> Result<u64, Error>;
+    // every dead letter when doc_id is None
+    fn purge_failed_pages(self: &mut Self, doc_id: Option<i64>) -> Result<u64, Error>;
+    // the writes for a batch go between begin and commit, so its pages are indexed completely or not at all
+    fn begin(self: &mut Self) -> Result<(), Error>;
+    fn commit(self: &mut Self) -> Result<(), Error>;
+    fn rollback(self: &mut Self) -> Result<(), Error>;
+    // doc_id -> fingerprint of the pages in doc_ids that are already indexed
+    fn indexed_fingerprints(self: &mut Self, doc_ids: &[i64]) -> Result<HashMap<i64, i64>, Error>;
+    // (doc_id, title, description, language, fingerprint)
+    fn write_indexed_pages(self: &mut Self, pages: &mut dyn Iterator<Item = (i64, String, String, String, i64)>) -> Result<(), Error>;
+    // replaces the words of every page in doc_ids with (doc_id, stem, surface form, weight, encoded positions).
+    // Only the rows that changed are touched
+    fn write_indexed_words(self: &mut Self, doc_ids: &[i64], words: &mut dyn Iterator<Item = (i64, String, String, u64, Vec<u8>)>) -> Result<(), Error>;
+    // (surface form, stem), lets the backend find the stem for a word typed in a query
+    fn write_term_forms(self: &mut Self, forms: &mut dyn Iterator<Item = (String, String)>) -> Result<(), Error>;
+    // replaces the statistics of every (doc_id, statistics) page, empty statistics remove the page. What that changes about
+    // the document frequencies and field totals is added to deltas for write_statistics_deltas
+    fn write_statistics(self: &mut Self, pages: &[(i64, PageStatistics)], deltas: &mut StatisticsDeltas) -> Result<(), Error>;
+    // moves the document frequencies and the totals of each field, once per transaction right before it commits
+    fn write_statistics_deltas(self: &mut Self, deltas: &StatisticsDeltas) -> Result<(), Error>;
+    // takes up to limit pages the crawler tombstoned out of the index, their statistics included. Returns (doc_id, url) of each
+    fn remove_tombstoned_pages(self: &mut Self, limit: i64) -> Result<Vec<(i64, String)>, Error>;
+    // up to limit pages of crawledtext with a doc_id above after that match the filter, in doc_id order
+    fn stored_pages(self: &mut Self, after: i64, filter: &ReindexFilter, limit: i64) -> Result<Vec<StoredPage>, Error>;
+    // writes reanalyzed pages to crawleddata for the indexer to pick up, skipping pages already waiting there.
+    // Returns how many were queued
+    fn queue_for_reindex(self: &mut Self, pages: &[crawled_page::CrawledPage]) -> Result<u64, Error>;
+    // rows whose weight or count is higher than the rest of the database says it should be
+    fn inflated_rows(self: &mut Self, weights: &WeightConfig) -> Result<Vec<InflatedRow>, Error>;
+    fn repair_inflated_rows(self: &mut Self, weights: &WeightConfig) -> Result<u64, Error>;
 }
 
+// what the weight of every indexed term adds up to from its term frequencies, $1 fields $2 multipliers $3 the default
+const EXPECTED_WEIGHTS: &str = "
+    WITH multipliers AS (
+        SELECT * FROM UNNEST($1::text[], $2::bigint[]) AS t (field, multiplier)
+    ), expected AS (
+        SELECT termfrequencies.doc_id, termfrequencies.word, SUM(termfrequencies.tf * COALESCE(multipliers.multiplier, $3))::bigint AS weight
+        FROM termfrequencies LEFT JOIN multipliers ON multipliers.field = termfrequencies.field
+        GROUP BY termfrequencies.doc_id, termfrequencies.word
+    )";
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum Error {
-    SQLError(Option<SqlState>)
+    // the code to match on and the message of the driver, which says what actually went wrong
+    SQLError(Option<SqlState>, String)
+}
+
+// postgres::Error only displays what kind of error it was, what the server said is in its source
+fn sql_error(error: &postgres::Error) -> Error {
+    let message = match std::error::Error::source(error) {
+        Some(t) => format!("{}: {}", error, t),
+        None => error.to_string()
+    };
+    return Error::SQLError(error.code().cloned(), message);
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            Error::SQLError(_, t) => write!(f, "{}", t)
+        };
+    }
 }
 
 pub struct PostgresDatabase {
-    client: Client
+    client: Client,
+    connection: String
 }
 
 impl PostgresDatabase {
     pub fn new(dbinfo: &PostgresDBInfo) -> Self {
         let string: String = format!("host={} user={} password={} dbname={}", dbinfo.host, dbinfo.username, dbinfo.password, dbinfo.dbname);
-        let new_client = Client::connect(&string, NoTls).unwrap();
+        let mut new_client = Client::connect(&string, NoTls).unwrap();
+        new_client.batch_execute(&format!("LISTEN {}", CRAWLED_PAGES_CHANNEL)).unwrap();
 
         let db: Self = Self{
             client: new_client,
+            connection: string
         };
         
         return db;
     }
+
+    fn reconnect(self: &mut Self) -> Result<(), Error> {
+        let mut new_client = match Client::connect(&self.connection, NoTls) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+        match new_client.batch_execute(&format!("LISTEN {}", CRAWLED_PAGES_CHANNEL)) {
+            Ok(_) => {},
+            Err(t) => return Err(sql_error(&t))
+        };
+        self.client = new_client;
+        return Ok(());
+    }
+
+    fn remove_tombstoned(self: &mut Self, limit: i64) -> Result<Vec<(i64, String)>, Error> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_secs() as i64;
+
+        let pages: Vec<(i64, String)> = match self.client.query(
+            "SELECT tombstones.doc_id, documents.url FROM tombstones
+            JOIN documents ON documents.id = tombstones.doc_id
+            WHERE tombstones.removed_at IS NULL
+            LIMIT $1 FOR UPDATE OF tombstones SKIP LOCKED",
+            &[&limit]
+        ) {
+            Ok(t) => t.iter().map(|row| (row.get::<&str, i64>("doc_id"), row.get::<&str, String>("url"))).collect(),
+            Err(t) => return Err(sql_error(&t))
+        };
+        let doc_ids: Vec<i64> = pages.iter().map(|t| t.0).collect();
+
+        // empty statistics take the page out of the global ones too
+        let mut deltas = StatisticsDeltas::new();
+        let empty: Vec<(i64, PageStatistics)> = doc_ids.iter().map(|t| (*t, PageStatistics::new())).collect();
+        match self.write_statistics(&empty, &mut deltas) {
+            Ok(_) => {},
+            Err(t) => return Err(t)
+        };
+
+        let result = self.client.execute(
+            "DELETE FROM indexedwords WHERE doc_id = ANY($1)",
+            &[&doc_ids]
+        ).and_then(|_| self.client.execute(
+            "DELETE FROM sitemetadata WHERE doc_id = ANY($1)",
+            &[&doc_ids]
+        )).and_then(|_| self.client.execute(
+            "UPDATE tombstones SET removed_at = $2 WHERE doc_id = ANY($1)",
+            &[&doc_ids, &now]
+        ));
+        match result {
+            Ok(_) => {},
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        match self.write_statistics_deltas(&deltas) {
+            Ok(_) => return Ok(pages),
+            Err(t) => return Err(t)
+        };
+    }
 }
 
 impl Database for PostgresDatabase {
-    fn crawled_page_len(self: &mut Self) -> u32 {
-        let response = match self.client.query_one(
-            "SELECT count(*) FROM crawleddata",
-            &[]
-        ) {
-            Ok(t) => t,
-            Err(_) => return 0
+    fn wait_for_pages(self: &mut Self, timeout: Duration) -> bool {
+        if self.client.is_closed() {
+            match self.reconnect() {
+                Ok(_) => info!("Reconnected to the database"),
+                Err(t) => {
+                    warn!("Couldnt reconnect to the database {:?}", t);
+                    std::thread::sleep(timeout);
+                }
+            };
+            return true;
+        }
+
+        let mut notifications = self.client.notifications();
+        match notifications.timeout_iter(timeout).next() {
+            Ok(Some(_)) => {},
+            Ok(None) => return false,
+            // the connection dropped while waiting, the next call reconnects
+            Err(t) => {
+                warn!("Stopped listening for crawled pages {:?}", t);
+                return true;
+            }
         };
 
-        return response.get::<&str, i64>("count") as u32;
+        // one pass indexes every page that is waiting, so the rest of the notifications are only drained
+        let _ = notifications.iter().count();
+        return true;
     }
 
-    fn get_crawled_page(self: &mut Self) -> Option<crawled_page::CrawledPage> {
-        let response = match self.client.query(
-            "SELECT * FROM (SELECT * FROM crawleddata LIMIT 1) AS sq LEFT JOIN crawledwords ON sq.url=crawledwords.url",
-            &[]
+    fn claim_crawled_pages(self: &mut Self, claim_timeout: u64, limit: i64) -> Vec<crawled_page::CrawledPage> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_millis() as i64;
+        let expired = now - (claim_timeout * 1000) as i64;
+
+        // SKIP LOCKED so indexers claiming at once get different pages instead of waiting on each other
+        let doc_ids: Vec<i64> = match self.client.query(
+            "UPDATE crawleddata SET claimed_at = $1
+            WHERE doc_id IN (
+                SELECT doc_id FROM crawleddata
+                WHERE (claimed_at IS NULL OR claimed_at < $2)
+                AND NOT EXISTS (
+                    SELECT 1 FROM failed_index
+                    WHERE failed_index.doc_id = crawleddata.doc_id AND (failed_index.retry_at IS NULL OR failed_index.retry_at > $1)
+                )
+                LIMIT $3 FOR UPDATE SKIP LOCKED
+            ) RETURNING doc_id",
+            &[&now, &expired, &limit]
         ) {
-            Ok(t) => t,
-            Err(_) => return None
+            Ok(t) => t.iter().map(|row| row.get::<&str, i64>("doc_id")).collect(),
+            Err(t) => {
+                error!("Couldnt claim crawled pages {:?}", t);
+                return vec![];
+            }
         };
+        if doc_ids.len() == 0 {
+            return vec![];
+        }
 
-        let mut crawled_data = crawled_page::CrawledPage {
-            url: response[0].get::<&str, String>("url"),
-            title: response[0].get::<&str, String>("title"),
-            description: response[0].get::<&str, String>("description"),
-            words: [].into()
+        let response = match self.client.query(
+            "SELECT * FROM crawleddata
+            JOIN documents ON documents.id = crawleddata.doc_id
+            LEFT JOIN crawledwords ON crawleddata.doc_id = crawledwords.doc_id
+            WHERE crawleddata.doc_id = ANY($1)
+            ORDER BY crawleddata.doc_id",
+            &[&doc_ids]
+        ) {
+            Ok(t) => t,
+            Err(t) => {
+                error!("Couldnt read claimed pages {:?}", t);
+                return vec![];
+            }
         };
 
+        let mut pages: Vec<crawled_page::CrawledPage> = vec![];
         for row in response {
-            crawled_data.words.push(
+            let doc_id = row.get::<&str, i64>("doc_id");
+            if pages.last().map(|t| t.doc_id) != Some(doc_id) {
+                pages.push(crawled_page::CrawledPage {
+                    doc_id: doc_id,
+                    claimed_at: now,
+                    url: row.get::<&str, String>("url"),
+                    title: row.get::<&str, String>("title"),
+                    description: row.get::<&str, String>("description"),
+                    // pages crawled before languages were detected dont have one
+                    language: row.get::<&str, Option<String>>("language").unwrap_or(String::from("und")),
+                    words: [].into()
+                });
+            }
+
+            // a page without words still comes back as one row from the left join
+            let word = match row.get::<&str, Option<String>>("word") {
+                Some(t) => t,
+                None => continue
+            };
+
+            pages.last_mut().unwrap().words.push(
                 crawled_page::Word{
-                    word:   row.get::<&str, String>("word"),
+                    // words crawled before surface forms were kept are their own surface
+                    surface: row.get::<&str, Option<String>>("surface").unwrap_or(word.clone()),
+                    word:   word,
                     parent: row.get::<&str, String>("parent"),
-                    count:  row.get::<&str, i32>("count")
+                    count:  row.get::<&str, i32>("count"),
+                    positions: row.get::<&str, Option<Vec<i32>>>("positions").unwrap_or_default().into_iter().map(|t| t as u32).collect()
                 }
             );
         }
 
+        return pages;
+    }
+
+    fn ack_crawled_pages(self: &mut Self, pages: &mut dyn Iterator<Item = (i64, i64)>) -> Result<(), Error> {
+        let (doc_ids, claims): (Vec<i64>, Vec<i64>) = pages.unzip();
+
+        // the crawler clears claimed_at when it writes a page again, so a newer version is left for the next pass
         match self.client.execute(
-            "DELETE FROM crawledurls WHERE url=$1;",
-            &[&crawled_data.url]
+            "WITH acked AS (
+                DELETE FROM crawleddata USING UNNEST($1::bigint[], $2::bigint[]) AS t (doc_id, claimed_at)
+                WHERE crawleddata.doc_id = t.doc_id AND crawleddata.claimed_at = t.claimed_at
+                RETURNING crawleddata.doc_id
+            ), words AS (
+                DELETE FROM crawledwords WHERE doc_id IN (SELECT doc_id FROM acked)
+            )
+            DELETE FROM failed_index WHERE doc_id IN (SELECT doc_id FROM acked)",
+            &[&doc_ids, &claims]
         ) {
-            Ok(_) => {}
-            Err(t) => println!("error: {:?}", t)
-        }
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn fail_crawled_page(self: &mut Self, page: &crawled_page::CrawledPage, error: &str, retry: &RetryConfig) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("").as_millis() as i64;
+
+        let mut transaction = match self.client.transaction() {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        let attempts = match transaction.query_opt("SELECT attempts FROM failed_index WHERE doc_id = $1 FOR UPDATE", &[&page.doc_id]) {
+            Ok(Some(t)) => t.get::<&str, i32>("attempts") + 1,
+            Ok(None) => 1,
+            Err(t) => return Err(sql_error(&t))
+        };
+        let retry_at = failed_index::retry_delay(attempts, retry).map(|t| now + (t * 1000) as i64);
+
+        match transaction.execute(
+            "INSERT INTO failed_index (doc_id, error, attempts, failed_at, retry_at) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (doc_id)
+            DO UPDATE SET
+                error = EXCLUDED.error,
+                attempts = EXCLUDED.attempts,
+                failed_at = EXCLUDED.failed_at,
+                retry_at = EXCLUDED.retry_at",
+            &[&page.doc_id, &error, &attempts, &now, &retry_at]
+        ) {
+            Ok(_) => {},
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        // only our own claim, if the crawler wrote the page again it is already unclaimed
+        match transaction.execute(
+            "UPDATE crawleddata SET claimed_at = NULL WHERE doc_id = $1 AND claimed_at = $2",
+            &[&page.doc_id, &page.claimed_at]
+        ) {
+            Ok(_) => {},
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        match transaction.commit() {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn failed_pages(self: &mut Self) -> Result<Vec<FailedPage>, Error> {
+        let response = match self.client.query(
+            "SELECT failed_index.*, documents.url FROM failed_index
+            JOIN documents ON documents.id = failed_index.doc_id
+            ORDER BY failed_index.failed_at DESC",
+            &[]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        return Ok(response.iter().map(|row| FailedPage {
+            doc_id: row.get::<&str, i64>("doc_id"),
+            url: row.get::<&str, String>("url"),
+            error: row.get::<&str, String>("error"),
+            attempts: row.get::<&str, i32>("attempts"),
+            failed_at: row.get::<&str, i64>("failed_at"),
+            retry_at: row.get::<&str, Option<i64>>("retry_at")
+        }).collect());
+    }
 
+    fn retry_failed_pages(self: &mut Self, doc_id: Option<i64>) -> Result<u64, Error> {
+        // a retried page starts over with all of its attempts
         match self.client.execute(
-            "DELETE FROM crawleddata WHERE url=$1;",
-            &[&crawled_data.url]
+            "UPDATE failed_index SET attempts = 0, retry_at = 0 WHERE $1::bigint IS NULL OR doc_id = $1",
+            &[&doc_id]
         ) {
-            Ok(_) => {}
-            Err(t) => println!("error: {:?}", t)
-        }
+            Ok(t) => return Ok(t),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn purge_failed_pages(self: &mut Self, doc_id: Option<i64>) -> Result<u64, Error> {
+        let response = match self.client.query_one(
+            "WITH purged AS (
+                DELETE FROM failed_index
+                WHERE ($1::bigint IS NULL AND retry_at IS NULL) OR doc_id = $1
+                RETURNING doc_id
+            ), data AS (
+                DELETE FROM crawleddata WHERE doc_id IN (SELECT doc_id FROM purged)
+            ), words AS (
+                DELETE FROM crawledwords WHERE doc_id IN (SELECT doc_id FROM purged)
+            )
+            SELECT count(*) FROM purged",
+            &[&doc_id]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        return Ok(response.get::<&str, i64>("count") as u64);
+    }
 
-        return Some(crawled_data);
+    fn begin(self: &mut Self) -> Result<(), Error> {
+        match self.client.batch_execute("BEGIN") {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn commit(self: &mut Self) -> Result<(), Error> {
+        match self.client.batch_execute("COMMIT") {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
     }
 
-    fn write_indexed_page(self: &mut Self, url: &str, title: &str, desc: &str) -> Result<(), Error> {
+    fn rollback(self: &mut Self) -> Result<(), Error> {
+        match self.client.batch_execute("ROLLBACK") {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn indexed_fingerprints(self: &mut Self, doc_ids: &[i64]) -> Result<HashMap<i64, i64>, Error> {
         match self.client.query(
-            "INSERT INTO sitemetadata VALUES ($1, $2, $3) 
-                ON CONFLICT (url)
+            "SELECT doc_id, fingerprint FROM sitemetadata WHERE doc_id = ANY($1) AND fingerprint IS NOT NULL",
+            &[&doc_ids]
+        ) {
+            Ok(t) => return Ok(t.iter().map(|row| (row.get::<&str, i64>("doc_id"), row.get::<&str, i64>("fingerprint"))).collect()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn write_indexed_pages(self: &mut Self, pages: &mut dyn Iterator<Item = (i64, String, String, String, i64)>) -> Result<(), Error> {
+        let mut doc_ids: Vec<i64> = vec![];
+        let mut titles: Vec<String> = vec![];
+        let mut descriptions: Vec<String> = vec![];
+        let mut languages: Vec<String> = vec![];
+        let mut fingerprints: Vec<i64> = vec![];
+
+        for (doc_id, title, description, language, fingerprint) in pages {
+            doc_ids.push(doc_id);
+            titles.push(title);
+            descriptions.push(description);
+            languages.push(language);
+            fingerprints.push(fingerprint);
+        }
+
+        match self.client.execute(
+            "INSERT INTO sitemetadata (doc_id, title, description, language, fingerprint)
+            SELECT * FROM UNNEST($1::bigint[], $2::text[], $3::text[], $4::text[], $5::bigint[])
+                ON CONFLICT (doc_id)
                 DO UPDATE SET
-                    title = $2,
-                    description = $3",
-            &[&url, &title, &desc]
+                    title = EXCLUDED.title,
+                    description = EXCLUDED.description,
+                    language = EXCLUDED.language,
+                    fingerprint = EXCLUDED.fingerprint",
+            &[&doc_ids, &titles, &descriptions, &languages, &fingerprints]
         ) {
             Ok(_) => return Ok(()),
-            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            Err(t) => return Err(sql_error(&t))
         };
     }
 
-    fn write_indexed_words(self: &mut Self, url: &str, word_iterator: &mut dyn Iterator<Item = (String, u64)>) -> Result<(), Error> {
+    fn write_indexed_words(self: &mut Self, page_ids: &[i64], word_iterator: &mut dyn Iterator<Item = (i64, String, String, u64, Vec<u8>)>) -> Result<(), Error> {
         let mut words: Vec<String> = vec![];
+        let mut surfaces: Vec<String> = vec![];
+        let mut positions: Vec<Vec<u8>> = vec![];
         let mut weights: Vec<i32> = vec![];
-        let mut urls: Vec<&str> = vec![];
+        let mut doc_ids: Vec<i64> = vec![];
         
-        for (word, value) in word_iterator {
+        for (doc_id, word, surface, value, encoded) in word_iterator {
             words.push(word);
+            surfaces.push(surface);
+            positions.push(encoded);
             weights.push(value as i32);
-            urls.push(url);
+            doc_ids.push(doc_id);
         }
 
+        // words the pages dont have anymore
         match self.client.execute(
-            "DELETE FROM indexedwords WHERE url = $1",
-            &[&url]
+            "DELETE FROM indexedwords
+            WHERE doc_id = ANY($1)
+            AND (doc_id, word) NOT IN (SELECT * FROM UNNEST($2::bigint[], $3::text[]))",
+            &[&page_ids, &doc_ids, &words]
         ) {
             Ok(_) => {},
-            Err(_) => {}
+            Err(t) => return Err(sql_error(&t))
         };
 
+        // new words are inserted, and the ones already there only updated when something about them changed
         match self.client.execute(
-            "INSERT INTO indexedwords (url, word, weight)
-            SELECT * FROM UNNEST($1::text[], $2::text[], $3::int[])
-            ON CONFLICT (url, word)
+            "INSERT INTO indexedwords (doc_id, word, weight, surface, positions)
+            SELECT * FROM UNNEST($1::bigint[], $2::text[], $3::int[], $4::text[], $5::bytea[])
+            ON CONFLICT (doc_id, word)
             DO UPDATE SET
-                weight = indexedwords.weight + EXCLUDED.weight;",
-            &[&urls, &words, &weights]
+                weight = EXCLUDED.weight,
+                surface = EXCLUDED.surface,
+                positions = EXCLUDED.positions
+            WHERE (indexedwords.weight, indexedwords.surface, indexedwords.positions)
+                IS DISTINCT FROM (EXCLUDED.weight, EXCLUDED.surface, EXCLUDED.positions);",
+            &[&doc_ids, &words, &weights, &surfaces, &positions]
         ) {
             Ok(_) => return Ok(()),
-            Err(t) => return Err(Error::SQLError(t.code().cloned()))
+            Err(t) => return Err(sql_error(&t))
         }
     }
-}
\ No newline at end of file
+
+    fn write_term_forms(self: &mut Self, forms: &mut dyn Iterator<Item = (String, String)>) -> Result<(), Error> {
+        let mut surfaces: Vec<String> = vec![];
+        let mut stems: Vec<String> = vec![];
+
+        for (surface, stem) in forms {
+            surfaces.push(surface);
+            stems.push(stem);
+        }
+
+        match self.client.execute(
+            "INSERT INTO termforms (surface, stem)
+            SELECT * FROM UNNEST($1::text[], $2::text[])
+            ON CONFLICT DO NOTHING;",
+            &[&surfaces, &stems]
+        ) {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        }
+    }
+
+    // runs inside the transaction of the batch, so the global statistics only move if the whole batch is written
+    fn write_statistics(self: &mut Self, pages: &[(i64, PageStatistics)], deltas: &mut StatisticsDeltas) -> Result<(), Error> {
+        let doc_ids: Vec<i64> = pages.iter().map(|t| t.0).collect();
+
+        // what the pages added to the global statistics last time they were indexed, taken back out by the deltas
+        let mut old_terms: HashMap<i64, Vec<String>> = HashMap::new();
+        match self.client.query("SELECT DISTINCT doc_id, word FROM termfrequencies WHERE doc_id = ANY($1)", &[&doc_ids]) {
+            Ok(t) => for row in t.iter() {
+                old_terms.entry(row.get::<&str, i64>("doc_id")).or_default().push(row.get::<&str, String>("word"));
+            },
+            Err(t) => return Err(sql_error(&t))
+        };
+        let mut old_fields: HashMap<i64, Vec<(String, i64)>> = HashMap::new();
+        match self.client.query("SELECT doc_id, field, length FROM fieldlengths WHERE doc_id = ANY($1)", &[&doc_ids]) {
+            Ok(t) => for row in t.iter() {
+                old_fields.entry(row.get::<&str, i64>("doc_id")).or_default().push((row.get::<&str, String>("field"), row.get::<&str, i64>("length")));
+            },
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        let mut tf_doc_ids: Vec<i64> = vec![];
+        let mut tf_words: Vec<&str> = vec![];
+        let mut tf_fields: Vec<&str> = vec![];
+        let mut tf_counts: Vec<i32> = vec![];
+        let mut length_doc_ids: Vec<i64> = vec![];
+        let mut length_fields: Vec<&str> = vec![];
+        let mut lengths: Vec<i64> = vec![];
+        for (doc_id, statistics) in pages.iter() {
+            statistics.add_deltas(old_terms.get(doc_id).map(|t| t.as_slice()).unwrap_or(&[]), old_fields.get(doc_id).map(|t| t.as_slice()).unwrap_or(&[]), deltas);
+
+            for ((word, field), count) in statistics.term_frequencies.iter() {
+                tf_doc_ids.push(*doc_id);
+                tf_words.push(word);
+                tf_fields.push(field);
+                tf_counts.push(*count as i32);
+            }
+            for (field, length) in statistics.field_lengths.iter() {
+                length_doc_ids.push(*doc_id);
+                length_fields.push(field);
+                lengths.push(*length as i64);
+            }
+        }
+
+        // the rows of a page are only ever written by the indexer that claimed it, so their order doesnt matter
+        let result = self.client.execute(
+            "DELETE FROM termfrequencies WHERE doc_id = ANY($1)",
+            &[&doc_ids]
+        ).and_then(|_| self.client.execute(
+            "INSERT INTO termfrequencies (doc_id, word, field, tf)
+            SELECT * FROM UNNEST($1::bigint[], $2::text[], $3::text[], $4::int[])",
+            &[&tf_doc_ids, &tf_words, &tf_fields, &tf_counts]
+        )).and_then(|_| self.client.execute(
+            "DELETE FROM fieldlengths WHERE doc_id = ANY($1)",
+            &[&doc_ids]
+        )).and_then(|_| self.client.execute(
+            "INSERT INTO fieldlengths (doc_id, field, length)
+            SELECT * FROM UNNEST($1::bigint[], $2::text[], $3::bigint[])",
+            &[&length_doc_ids, &length_fields, &lengths]
+        ));
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn write_statistics_deltas(self: &mut Self, deltas: &StatisticsDeltas) -> Result<(), Error> {
+        let mut words: Vec<&str> = vec![];
+        let mut dfs: Vec<i32> = vec![];
+        let mut dropped: Vec<&str> = vec![];
+        for (word, delta) in deltas.terms.iter().filter(|(_, t)| **t != 0) {
+            words.push(word);
+            dfs.push(*delta as i32);
+            if *delta < 0 {
+                dropped.push(word);
+            }
+        }
+
+        let mut fields: Vec<&str> = vec![];
+        let mut documents: Vec<i64> = vec![];
+        let mut lengths: Vec<i64> = vec![];
+        for (field, (document_delta, length_delta)) in deltas.fields.iter() {
+            fields.push(field);
+            documents.push(*document_delta);
+            lengths.push(*length_delta);
+        }
+
+        // the rows are locked in the order they are upserted, which has to be the same for every indexer. Terms that
+        // dropped to no documents are deleted after, their rows are already locked by then
+        let result = self.client.execute(
+            "INSERT INTO documentfrequencies (word, df)
+            SELECT * FROM UNNEST($1::text[], $2::int[]) AS t (word, df)
+            ORDER BY word COLLATE \"C\"
+            ON CONFLICT (word)
+            DO UPDATE SET df = documentfrequencies.df + EXCLUDED.df",
+            &[&words, &dfs]
+        ).and_then(|_| self.client.execute(
+            "DELETE FROM documentfrequencies WHERE word = ANY($1) AND df <= 0",
+            &[&dropped]
+        )).and_then(|_| self.client.execute(
+            "INSERT INTO fieldstats (field, documents, total_length)
+            SELECT * FROM UNNEST($1::text[], $2::bigint[], $3::bigint[]) AS t (field, documents, total_length)
+            ORDER BY field COLLATE \"C\"
+            ON CONFLICT (field)
+            DO UPDATE SET
+                documents = fieldstats.documents + EXCLUDED.documents,
+                total_length = fieldstats.total_length + EXCLUDED.total_length",
+            &[&fields, &documents, &lengths]
+        ));
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn remove_tombstoned_pages(self: &mut Self, limit: i64) -> Result<Vec<(i64, String)>, Error> {
+        match self.begin() {
+            Ok(_) => {},
+            Err(t) => return Err(t)
+        };
+        match self.remove_tombstoned(limit) {
+            Ok(t) => match self.commit() {
+                Ok(_) => return Ok(t),
+                Err(t) => return Err(t)
+            },
+            Err(t) => {
+                let _ = self.rollback();
+                return Err(t);
+            }
+        };
+    }
+
+    fn stored_pages(self: &mut Self, after: i64, filter: &ReindexFilter, limit: i64) -> Result<Vec<StoredPage>, Error> {
+        let response = match self.client.query(
+            "SELECT crawledtext.*, documents.url FROM crawledtext
+            JOIN documents ON documents.id = crawledtext.doc_id
+            WHERE crawledtext.doc_id > $1
+            AND ($2::text IS NULL
+                OR substring(documents.url from '^[a-z]+://([^/:?#]+)') = $2
+                OR substring(documents.url from '^[a-z]+://([^/:?#]+)') LIKE '%.' || $2)
+            AND ($3::bigint IS NULL OR crawledtext.crawled_at >= $3)
+            AND ($4::bigint IS NULL OR crawledtext.crawled_at < $4)
+            ORDER BY crawledtext.doc_id
+            LIMIT $5",
+            &[&after, &filter.domain, &filter.since, &filter.until, &limit]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        return Ok(response.iter().map(|row| StoredPage {
+            doc_id: row.get::<&str, i64>("doc_id"),
+            url: row.get::<&str, String>("url"),
+            title: row.get::<&str, Option<String>>("title").unwrap_or_default(),
+            description: row.get::<&str, Option<String>>("description").unwrap_or_default(),
+            language: row.get::<&str, Option<String>>("language").unwrap_or(String::from("und")),
+            text: row.get::<&str, Option<Vec<u8>>>("text").unwrap_or_default()
+        }).collect());
+    }
+
+    fn queue_for_reindex(self: &mut Self, pages: &[crawled_page::CrawledPage]) -> Result<u64, Error> {
+        if pages.len() == 0 {
+            return Ok(0);
+        }
+
+        let doc_ids: Vec<i64> = pages.iter().map(|t| t.doc_id).collect();
+        let titles: Vec<&str> = pages.iter().map(|t| t.title.as_str()).collect();
+        let descriptions: Vec<&str> = pages.iter().map(|t| t.description.as_str()).collect();
+        let languages: Vec<&str> = pages.iter().map(|t| t.language.as_str()).collect();
+
+        let mut word_doc_ids: Vec<i64> = vec![];
+        let mut parents: Vec<&str> = vec![];
+        let mut words: Vec<&str> = vec![];
+        let mut counts: Vec<i32> = vec![];
+        let mut surfaces: Vec<&str> = vec![];
+        // as a string per word, the same way the crawler writes them
+        let mut positions: Vec<String> = vec![];
+        for page in pages.iter() {
+            for word in page.words.iter() {
+                word_doc_ids.push(page.doc_id);
+                parents.push(&word.parent);
+                words.push(&word.word);
+                counts.push(word.count);
+                surfaces.push(&word.surface);
+                positions.push(format!("{{{}}}", word.positions.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(",")));
+            }
+        }
+
+        // a page already in crawleddata is a newer crawl or a claim in progress, either way it is left to the indexer as is
+        let response = match self.client.query_one(
+            "WITH pages AS (
+                INSERT INTO crawleddata (doc_id, title, description, language)
+                SELECT * FROM UNNEST($1::bigint[], $2::text[], $3::text[], $4::text[])
+                ON CONFLICT (doc_id) DO NOTHING
+                RETURNING doc_id
+            ), words AS (
+                INSERT INTO crawledwords (doc_id, parent, word, count, surface, positions)
+                SELECT doc_id, parent, word, count, surface, positions::int[] FROM UNNEST($5::bigint[], $6::text[], $7::text[], $8::int[], $9::text[], $10::text[])
+                    AS t (doc_id, parent, word, count, surface, positions)
+                WHERE doc_id IN (SELECT doc_id FROM pages)
+                ON CONFLICT (doc_id, parent, word)
+                DO UPDATE SET count = EXCLUDED.count, surface = EXCLUDED.surface, positions = EXCLUDED.positions
+            ), failed AS (
+                DELETE FROM failed_index WHERE doc_id IN (SELECT doc_id FROM pages)
+            )
+            SELECT count(*) FROM pages",
+            &[&doc_ids, &titles, &descriptions, &languages, &word_doc_ids, &parents, &words, &counts, &surfaces, &positions]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+        let queued = response.get::<&str, i64>("count") as u64;
+
+        // wakes up the indexer, which may well be this one listening on another connection
+        match self.client.execute("SELECT pg_notify($1, $2)", &[&CRAWLED_PAGES_CHANNEL, &doc_ids[0].to_string()]) {
+            Ok(_) => return Ok(queued),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+
+    fn inflated_rows(self: &mut Self, weights: &WeightConfig) -> Result<Vec<InflatedRow>, Error> {
+        let (fields, multipliers, default) = consistency::multipliers(weights);
+
+        let weight_rows = match self.client.query(
+            &format!("{}
+            SELECT indexedwords.doc_id, documents.url, indexedwords.word, indexedwords.weight::bigint AS found, expected.weight AS expected
+            FROM indexedwords
+            JOIN expected ON expected.doc_id = indexedwords.doc_id AND expected.word = indexedwords.word
+            JOIN documents ON documents.id = indexedwords.doc_id
+            WHERE indexedwords.weight > expected.weight
+            ORDER BY indexedwords.doc_id, indexedwords.word", EXPECTED_WEIGHTS),
+            &[&fields, &multipliers, &default]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        // every occurrence of a word has a position, so the count cant be higher than the number of positions
+        let count_rows = match self.client.query(
+            "SELECT crawledwords.doc_id, documents.url, crawledwords.word, crawledwords.count::bigint AS found, cardinality(crawledwords.positions)::bigint AS expected
+            FROM crawledwords
+            JOIN documents ON documents.id = crawledwords.doc_id
+            WHERE cardinality(crawledwords.positions) > 0 AND crawledwords.count > cardinality(crawledwords.positions)
+            ORDER BY crawledwords.doc_id, crawledwords.word",
+            &[]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        let rows = weight_rows.iter().map(|row| ("indexedwords", row)).chain(count_rows.iter().map(|row| ("crawledwords", row)));
+        return Ok(rows.map(|(table, row)| InflatedRow {
+            table: table,
+            doc_id: row.get::<&str, i64>("doc_id"),
+            url: row.get::<&str, String>("url"),
+            word: row.get::<&str, String>("word"),
+            found: row.get::<&str, i64>("found"),
+            expected: row.get::<&str, i64>("expected")
+        }).collect());
+    }
+
+    fn repair_inflated_rows(self: &mut Self, weights: &WeightConfig) -> Result<u64, Error> {
+        let (fields, multipliers, default) = consistency::multipliers(weights);
+
+        let mut transaction = match self.client.transaction() {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        let weights_repaired = match transaction.execute(
+            &format!("{}
+            UPDATE indexedwords SET weight = expected.weight
+            FROM expected
+            WHERE expected.doc_id = indexedwords.doc_id AND expected.word = indexedwords.word
+            AND indexedwords.weight > expected.weight", EXPECTED_WEIGHTS),
+            &[&fields, &multipliers, &default]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        let counts_repaired = match transaction.execute(
+            "UPDATE crawledwords SET count = cardinality(positions)
+            WHERE cardinality(positions) > 0 AND count > cardinality(positions)",
+            &[]
+        ) {
+            Ok(t) => t,
+            Err(t) => return Err(sql_error(&t))
+        };
+
+        match transaction.commit() {
+            Ok(_) => return Ok(weights_repaired + counts_repaired),
+            Err(t) => return Err(sql_error(&t))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs against the postgres in INDEXER_TEST_DATABASE, a connection string like "host=localhost user=postgres", in a
+    // schema of its own with the tables the crawler would have made. Skipped when it isnt set
+    fn test_database(name: &str) -> Option<PostgresDatabase> {
+        let connection = match std::env::var("INDEXER_TEST_DATABASE") {
+            Ok(t) => t,
+            Err(_) => {
+                eprintln!("INDEXER_TEST_DATABASE isnt set, skipping {}", name);
+                return None;
+            }
+        };
+        let mut client = Client::connect(&connection, NoTls).unwrap();
+        client.batch_execute(&format!("
+            DROP SCHEMA IF EXISTS {0} CASCADE;
+            CREATE SCHEMA {0};
+            SET search_path TO {0};
+            CREATE TABLE documents (id bigint PRIMARY KEY, url text NOT NULL UNIQUE);
+            CREATE TABLE crawleddata (doc_id bigint PRIMARY KEY, title varchar(512), description varchar(1024), encoding varchar(32), language varchar(16), claimed_at bigint);
+            CREATE TABLE crawledwords (doc_id bigint, parent varchar(512), word varchar(64), count integer, surface varchar(512), positions integer[], PRIMARY KEY (doc_id, word, parent));
+            CREATE TABLE failed_index (doc_id bigint PRIMARY KEY, error text, attempts integer, failed_at bigint, retry_at bigint);
+            CREATE TABLE termfrequencies (doc_id bigint, word varchar(512), field varchar(512), tf integer, PRIMARY KEY (doc_id, word, field));
+            CREATE TABLE fieldlengths (doc_id bigint, field varchar(512), length bigint, PRIMARY KEY (doc_id, field));
+            CREATE TABLE documentfrequencies (word varchar(512) PRIMARY KEY, df integer);
+            CREATE TABLE fieldstats (field varchar(512) PRIMARY KEY, documents bigint, total_length bigint);
+            CREATE TABLE indexedwords (doc_id bigint, word varchar(512), weight integer, surface varchar(512), positions bytea, PRIMARY KEY (doc_id, word));
+        ", name)).unwrap();
+        return Some(PostgresDatabase { client: client, connection: connection });
+    }
+
+    #[test]
+    fn failed_pages_wait_for_retry_and_purge() {
+        let mut db = match test_database("indexer_test_failed_pages") {
+            Some(t) => t,
+            None => return
+        };
+        db.client.batch_execute("
+            INSERT INTO documents VALUES (1, 'https://example.com/1'), (2, 'https://example.com/2'), (3, 'https://example.com/3');
+            INSERT INTO crawleddata (doc_id, title, description, language) VALUES (1, 'One', '', 'en'), (2, 'Two', '', 'en'), (3, 'Three', '', 'en');
+            INSERT INTO crawledwords VALUES (1, 'p', 'one', 1, 'One', '{0}');
+        ").unwrap();
+        let retry = RetryConfig { max_attempts: 2, backoff: 3600, max_backoff: 3600 };
+
+        let pages = db.claim_crawled_pages(60, 10);
+        assert_eq!(pages.iter().map(|t| t.doc_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        // a claimed page isnt claimed twice
+        assert_eq!(db.claim_crawled_pages(60, 10).len(), 0);
+
+        // the error the driver gave is what ends up in failed_index
+        let error = db.stored_pages(0, &ReindexFilter { domain: None, since: None, until: None }, 10).err().unwrap();
+        assert!(error.to_string().contains("crawledtext"), "{}", error);
+
+        db.fail_crawled_page(&pages[0], &error.to_string(), &retry).unwrap();
+        db.fail_crawled_page(&pages[1], "first", &retry).unwrap();
+        db.fail_crawled_page(&pages[1], "second", &retry).unwrap();
+        db.ack_crawled_pages(&mut [(3, pages[2].claimed_at)].into_iter()).unwrap();
+
+        // one page is waiting for its backoff and the other ran out of attempts, neither is claimed
+        assert_eq!(db.claim_crawled_pages(60, 10).len(), 0);
+        let failed = db.failed_pages().unwrap();
+        let first = failed.iter().find(|t| t.doc_id == 1).unwrap();
+        let second = failed.iter().find(|t| t.doc_id == 2).unwrap();
+        assert_eq!(first.error, error.to_string());
+        assert_eq!(first.attempts, 1);
+        assert!(first.retry_at.unwrap() >= first.failed_at + 3600 * 1000);
+        assert_eq!((second.error.as_str(), second.attempts, second.retry_at), ("second", 2, None));
+
+        // purging everything only takes the dead letters
+        assert_eq!(db.purge_failed_pages(None).unwrap(), 1);
+        assert_eq!(db.failed_pages().unwrap().len(), 1);
+
+        assert_eq!(db.retry_failed_pages(Some(1)).unwrap(), 1);
+        let pages = db.claim_crawled_pages(60, 10);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].words[0].surface, "One");
+        db.ack_crawled_pages(&mut [(1, pages[0].claimed_at)].into_iter()).unwrap();
+        assert_eq!(db.failed_pages().unwrap().len(), 0);
+
+        db.client.batch_execute("DROP SCHEMA indexer_test_failed_pages CASCADE").unwrap();
+    }
+
+    #[test]
+    fn inflated_rows_found_and_repaired() {
+        let mut db = match test_database("indexer_test_inflated_rows") {
+            Some(t) => t,
+            None => return
+        };
+        // run was added to twice, walk is only in a field the weights dont list so it is weighed with the default
+        db.client.batch_execute("
+            INSERT INTO documents VALUES (1, 'https://example.com/1');
+            INSERT INTO termfrequencies VALUES (1, 'run', 'title', 1), (1, 'run', 'p', 2), (1, 'walk', 'p', 1);
+            INSERT INTO indexedwords (doc_id, word, weight) VALUES (1, 'run', 18), (1, 'walk', 2);
+            INSERT INTO crawledwords VALUES (1, 'p', 'run', 4, 'run', '{0,1}'), (1, 'p', 'walk', 1, 'walk', '{2}');
+        ").unwrap();
+        let weights = WeightConfig {
+            fields: HashMap::from([("title".to_string(), 5), ("default".to_string(), 2)]),
+            min_weight: 0,
+            max_terms_per_page: 0
+        };
+
+        let rows: Vec<(&str, i64, String, String, i64, i64)> = db.inflated_rows(&weights).unwrap().into_iter()
+            .map(|t| (t.table, t.doc_id, t.url, t.word, t.found, t.expected)).collect();
+        assert_eq!(rows, vec![
+            ("indexedwords", 1, "https://example.com/1".to_string(), "run".to_string(), 18, 9),
+            ("crawledwords", 1, "https://example.com/1".to_string(), "run".to_string(), 4, 2)
+        ]);
+
+        assert_eq!(db.repair_inflated_rows(&weights).unwrap(), 2);
+        assert_eq!(db.inflated_rows(&weights).unwrap().len(), 0);
+        let weights: Vec<(String, i32)> = db.client.query("SELECT word, weight FROM indexedwords ORDER BY word", &[]).unwrap().iter()
+            .map(|row| (row.get::<&str, String>("word"), row.get::<&str, i32>("weight"))).collect();
+        assert_eq!(weights, vec![("run".to_string(), 9), ("walk".to_string(), 2)]);
+        let count: i32 = db.client.query_one("SELECT count FROM crawledwords WHERE word = 'run'", &[]).unwrap().get(0);
+        assert_eq!(count, 2);
+
+        db.client.batch_execute("DROP SCHEMA indexer_test_inflated_rows CASCADE").unwrap();
+    }
+
+    fn statistics(terms: &[(&str, &str, u64)]) -> PageStatistics {
+        let mut statistics = PageStatistics::new();
+        for (term, field, count) in terms {
+            statistics.add(term, field, *count);
+        }
+        return statistics;
+    }
+
+    fn write_batch(db: &mut PostgresDatabase, pages: &[(i64, PageStatistics)]) {
+        let mut deltas = StatisticsDeltas::new();
+        db.begin().unwrap();
+        db.write_statistics(pages, &mut deltas).unwrap();
+        db.write_statistics_deltas(&deltas).unwrap();
+        db.commit().unwrap();
+    }
+
+    fn document_frequencies(db: &mut PostgresDatabase) -> Vec<(String, i32)> {
+        return db.client.query("SELECT word, df FROM documentfrequencies ORDER BY word", &[]).unwrap().iter()
+            .map(|row| (row.get::<&str, String>("word"), row.get::<&str, i32>("df"))).collect();
+    }
+
+    fn field_stats(db: &mut PostgresDatabase) -> Vec<(String, i64, i64)> {
+        return db.client.query("SELECT * FROM fieldstats ORDER BY field", &[]).unwrap().iter()
+            .map(|row| (row.get::<&str, String>("field"), row.get::<&str, i64>("documents"), row.get::<&str, i64>("total_length"))).collect();
+    }
+
+    #[test]
+    fn statistics_move_once_per_batch() {
+        let mut db = match test_database("indexer_test_statistics") {
+            Some(t) => t,
+            None => return
+        };
+
+        write_batch(&mut db, &[
+            (1, statistics(&[("run", "p", 2), ("fast", "p", 1)])),
+            (2, statistics(&[("run", "h1", 1)]))
+        ]);
+        assert_eq!(document_frequencies(&mut db), vec![("fast".to_string(), 1), ("run".to_string(), 2)]);
+        assert_eq!(field_stats(&mut db), vec![("*".to_string(), 2, 4), ("h1".to_string(), 1, 1), ("p".to_string(), 1, 3)]);
+
+        // page 1 indexed again with other words and page 2 removed, terms that no page has any more are dropped
+        write_batch(&mut db, &[
+            (1, statistics(&[("walk", "p", 1)])),
+            (2, PageStatistics::new())
+        ]);
+        assert_eq!(document_frequencies(&mut db), vec![("walk".to_string(), 1)]);
+        assert_eq!(field_stats(&mut db), vec![("*".to_string(), 1, 1), ("h1".to_string(), 0, 0), ("p".to_string(), 1, 1)]);
+        let tfs: i64 = db.client.query_one("SELECT count(*) FROM termfrequencies", &[]).unwrap().get(0);
+        assert_eq!(tfs, 1);
+
+        db.client.batch_execute("DROP SCHEMA indexer_test_statistics CASCADE").unwrap();
+    }
+
+    #[test]
+    fn statistics_of_a_failed_batch_are_written_once_by_the_fallback() {
+        let mut db = match test_database("indexer_test_statistics_fallback") {
+            Some(t) => t,
+            None => return
+        };
+        let pages = [
+            (1, statistics(&[("run", "p", 2), ("fast", "p", 1)])),
+            (2, statistics(&[("run", "p", 1)]))
+        ];
+
+        // the batch fails after its deltas went in and is rolled back, then every page is written in a transaction of its own
+        let mut deltas = StatisticsDeltas::new();
+        db.begin().unwrap();
+        db.write_statistics(&pages, &mut deltas).unwrap();
+        db.write_statistics_deltas(&deltas).unwrap();
+        db.rollback().unwrap();
+        assert_eq!(document_frequencies(&mut db), vec![]);
+
+        for page in pages.iter() {
+            write_batch(&mut db, std::slice::from_ref(page));
+        }
+        assert_eq!(document_frequencies(&mut db), vec![("fast".to_string(), 1), ("run".to_string(), 2)]);
+        assert_eq!(field_stats(&mut db), vec![("*".to_string(), 2, 4), ("p".to_string(), 2, 4)]);
+
+        db.client.batch_execute("DROP SCHEMA indexer_test_statistics_fallback CASCADE").unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::RetryConfig;
+use crate::database;
+
+// a page that failed to index, kept in failed_index until it indexes or is purged
+pub struct FailedPage {
+    pub doc_id: i64,
+    pub url: String,
+    pub error: String,
+    pub attempts: i32,
+    // unix millis
+    pub failed_at: i64,
+    // unix millis, none once the page is out of attempts and only the cli will try it again
+    pub retry_at: Option<i64>
+}
+
+// seconds to wait before the next attempt, doubling from retry.backoff up to retry.max_backoff.
+// None once the page used up all of its attempts
+pub fn retry_delay(attempts: i32, retry: &RetryConfig) -> Option<u64> {
+    if attempts >= retry.max_attempts {
+        return None;
+    }
+    let doublings = (attempts.max(1) - 1).min(32) as u32;
+    return Some(retry.backoff.saturating_mul(1 << doublings).min(retry.max_backoff));
+}
+
+// indexer failed list
+// indexer failed retry [doc_id]   tries a page again on the next pass, every failed page without a doc_id
+// indexer failed purge [doc_id]   drops a page and its crawled data, every dead letter without a doc_id
+pub fn run_command(args: &[String], db: &mut dyn database::Database) -> Result<(), String> {
+    let doc_id = match args.get(1) {
+        Some(t) => match t.parse::<i64>() {
+            Ok(t) => Some(t),
+            Err(_) => return Err(format!("'{}' is not a doc_id", t))
+        },
+        None => None
+    };
+
+    match args.first().map(|t| t.as_str()) {
+        Some("list") => {
+            let pages = match db.failed_pages() {
+                Ok(t) => t,
+                Err(t) => return Err(format!("{:?}", t))
+            };
+            for page in pages {
+                let retry = match page.retry_at {
+                    Some(t) => format!("retry at {}", t),
+                    None => String::from("dead")
+                };
+                println!("{}\t{}\t{} attempt(s)\tfailed at {}\t{}\t{}", page.doc_id, page.url, page.attempts, page.failed_at, retry, page.error);
+            }
+            return Ok(());
+        },
+        Some("retry") => match db.retry_failed_pages(doc_id) {
+            Ok(t) => {
+                println!("{} page(s) will be retried", t);
+                return Ok(());
+            },
+            Err(t) => return Err(format!("{:?}", t))
+        },
+        Some("purge") => match db.purge_failed_pages(doc_id) {
+            Ok(t) => {
+                println!("{} page(s) purged", t);
+                return Ok(());
+            },
+            Err(t) => return Err(format!("{:?}", t))
+        },
+        _ => return Err(String::from("usage: indexer failed <list | retry [doc_id] | purge [doc_id]>"))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_until_the_attempts_run_out() {
+        let retry = RetryConfig { max_attempts: 5, backoff: 60, max_backoff: 200 };
+
+        assert_eq!(retry_delay(1, &retry), Some(60));
+        assert_eq!(retry_delay(2, &retry), Some(120));
+        assert_eq!(retry_delay(3, &retry), Some(200));
+        assert_eq!(retry_delay(4, &retry), Some(200));
+        assert_eq!(retry_delay(5, &retry), None);
+    }
+}
@@ -1,77 +1,206 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::ops::Add;
 
 use crate::crawled_page;
+use crate::batch::IndexBatch;
 use crate::database;
-use crate::dictionary;
+use crate::config::WeightConfig;
+use crate::statistics::PageStatistics;
+use crate::segment::{SegmentError, StoredDocument};
+use crate::segment_index::SegmentIndex;
+
+use analysis::Analyzers;
+use log::warn;
+use xxhash_rust::xxh3::xxh3_64;
 
 pub trait IndexedPage {
-    fn from_crawled_page(self: &mut Self, page: crawled_page::CrawledPage, dict: &dyn dictionary::Dictionary);
-    fn consume_into_db(self: &mut Self, db: &mut dyn database::Database) -> Result<(), database::Error>;
+    fn from_crawled_page(self: &mut Self, page: crawled_page::CrawledPage, analyzers: &Analyzers, weights: &WeightConfig);
+    fn add_to_batch(self: &mut Self, batch: &mut IndexBatch);
+    // a hash of everything the page writes to the index, a page with the same fingerprint as last time is left alone
+    fn fingerprint(self: &Self) -> i64;
+    fn write_into_segments(self: &Self, segments: &SegmentIndex) -> Result<(), SegmentError>;
 }
 
 pub struct BasicIndexedPage {
+    pub doc_id: i64,
     pub url: String,
     pub title: String,
     pub description: String,
-    pub words: HashMap<String, u64>
+    pub language: String,
+    // keyed by term, as the crawler analyzed it
+    pub words: HashMap<String, u64>,
+    // every way a term was written on the page and the weight it had, the heaviest one is kept for highlighting
+    pub surface_forms: HashMap<String, HashMap<String, u64>>,
+    // (folded surface form, term), what the backend looks a query word up by
+    pub term_forms: HashSet<(String, String)>,
+    // every position a term is at, across all fields
+    pub positions: HashMap<String, Vec<u32>>,
+    pub statistics: PageStatistics
 }
 
 impl IndexedPage for BasicIndexedPage {
-    fn from_crawled_page(self: &mut Self, page: crawled_page::CrawledPage, dict: &dyn dictionary::Dictionary) {
+    fn from_crawled_page(self: &mut Self, page: crawled_page::CrawledPage, analyzers: &Analyzers, weights: &WeightConfig) {
+        self.doc_id = page.doc_id;
         self.url = page.url;
         self.title = page.title;
         self.description = page.description;
+        self.language = page.language;
 
+        // the crawler already ran the words through the analyzers, stopwords are gone and the rest are terms
         for word in page.words {
-            if dict.get_word_status(&word.word) == dictionary::WordType::StopWord {
-                continue
-            }
-
-            let multiplier = match word.parent.as_str() {
-                "title" => 30,
-                "h1" => 20,
-                "h2" => 18,
-                "h3" => 16,
-                "h4" => 14,
-                "h5" => 12,
-                "h6" => 10,
-                "a" => 5,
-                _ => 1
+            let multiplier = match weights.fields.get(&word.parent).or_else(|| weights.fields.get("default")) {
+                Some(t) => *t,
+                None => 1
             };
 
-            self.words.insert_or_sum(word.word, (word.count * multiplier) as u64);
+            let weight = word.count as u64 * multiplier;
+            let term = word.word;
+
+            self.statistics.add(&term, &word.parent, word.count as u64);
+            self.positions.entry(term.clone()).or_default().extend(word.positions);
+            self.words.insert_or_sum(term.clone(), weight);
+            self.term_forms.insert((analyzers.fold_query(&word.surface), term.clone()));
+            self.surface_forms.entry(term).or_default().insert_or_sum(word.surface, weight);
         }
 
         for (word, score) in self.words.clone().iter() {
-            if *score <= 2 {
+            if *score < weights.min_weight {
                 self.words.remove(word);
+                self.surface_forms.remove(word);
+            }
+        }
+
+        // heaviest first, ties broken by the term so the same page always keeps the same terms
+        if weights.max_terms_per_page > 0 && self.words.len() > weights.max_terms_per_page {
+            let mut ranked: Vec<(String, u64)> = self.words.drain().collect();
+            ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+            for (word, _) in ranked.split_off(weights.max_terms_per_page) {
+                self.surface_forms.remove(&word);
             }
+            self.words = ranked.into_iter().collect();
         }
     }
 
-    fn consume_into_db(self: &mut Self, db: &mut dyn database::Database) -> Result<(), database::Error> {
-        match db.write_indexed_page(&self.url, &self.title, &self.description) {
-            Ok(_) => {}
-            Err(t) => return Err(t)
-        };
-        match db.write_indexed_words(&self.url, &mut self.words.clone().into_iter()) {
-            Ok(_) => return Ok(()),
-            Err(t) => return Err(t)
+    fn add_to_batch(self: &mut Self, batch: &mut IndexBatch) {
+        self.statistics.retain_terms(&self.words);
+
+        batch.doc_ids.push(self.doc_id);
+        batch.pages.push((self.doc_id, self.title.clone(), self.description.clone(), self.language.clone(), self.fingerprint()));
+        let words = &self.words;
+        batch.term_forms.extend(self.term_forms.iter().filter(|(_, term)| words.contains_key(term)).cloned());
+        batch.statistics.push((self.doc_id, self.statistics.clone()));
+
+        let surface_forms = &self.surface_forms;
+        let positions = &self.positions;
+        batch.words.extend(self.words.iter().map(|(stem, weight)| {
+            let encoded = analysis::positions::encode(positions.get(stem).map(|t| t.as_slice()).unwrap_or(&[]));
+            (self.doc_id, stem.clone(), Self::surface_form(surface_forms, stem), *weight, encoded)
+        }));
+    }
+
+    // the terms come out of a hashmap, so they are sorted to keep the hash stable between passes
+    fn fingerprint(self: &Self) -> i64 {
+        let mut terms: Vec<(&String, &u64)> = self.words.iter().collect();
+        terms.sort();
+
+        let mut content: Vec<u8> = vec![];
+        content.extend_from_slice(self.title.as_bytes());
+        content.push(0);
+        content.extend_from_slice(self.description.as_bytes());
+        content.push(0);
+        content.extend_from_slice(self.language.as_bytes());
+        for (term, weight) in terms {
+            content.push(0);
+            content.extend_from_slice(term.as_bytes());
+            content.push(0);
+            content.extend_from_slice(Self::surface_form(&self.surface_forms, term).as_bytes());
+            content.extend_from_slice(&weight.to_le_bytes());
+            content.extend_from_slice(&analysis::positions::encode(self.positions.get(term).map(|t| t.as_slice()).unwrap_or(&[])));
+        }
+
+        return xxh3_64(&content) as i64;
+    }
+
+    fn write_into_segments(self: &Self, segments: &SegmentIndex) -> Result<(), SegmentError> {
+        let document = StoredDocument {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            language: self.language.clone()
         };
+        let positions = &self.positions;
+        return segments.add(document, &mut self.words.iter().map(|(term, weight)| {
+            (term.clone(), *weight, analysis::positions::encode(positions.get(term).map(|t| t.as_slice()).unwrap_or(&[])))
+        }));
     }
 }
 
 impl BasicIndexedPage {
     pub fn new() -> Self {
         return BasicIndexedPage {
+            doc_id: 0,
             url: String::from(""),
             title: String::from(""),
             description: String::from(""),
-            words: [].into()
+            language: String::from(""),
+            words: [].into(),
+            surface_forms: [].into(),
+            term_forms: [].into(),
+            positions: [].into(),
+            statistics: PageStatistics::new()
         };
     }
+
+    fn surface_form(surface_forms: &HashMap<String, HashMap<String, u64>>, stem: &str) -> String {
+        return match surface_forms.get(stem).and_then(|forms| forms.iter().max_by_key(|(surface, weight)| (**weight, std::cmp::Reverse(*surface)))) {
+            Some((surface, _)) => surface.clone(),
+            None => stem.to_string()
+        };
+    }
+}
+
+// weighs a batch of crawled pages, split over workers threads
+pub fn index_pages(pages: &[crawled_page::CrawledPage], analyzers: &Analyzers, weights: &WeightConfig, workers: usize) -> Vec<BasicIndexedPage> {
+    let chunk_size = pages.len().div_ceil(workers.max(1)).max(1);
+
+    return std::thread::scope(|scope| {
+        let handles: Vec<_> = pages.chunks(chunk_size).map(|chunk| scope.spawn(move || {
+            return chunk.iter().map(|page| {
+                let mut indexed = BasicIndexedPage::new();
+                indexed.from_crawled_page(page.clone(), analyzers, weights);
+                return indexed;
+            }).collect::<Vec<BasicIndexedPage>>();
+        })).collect();
+
+        // chunks are joined in order, so the pages come back in the order they were given
+        return handles.into_iter().flat_map(|t| t.join().expect("An indexer worker panicked")).collect();
+    });
+}
+
+// writes the pages that changed in one batch. If the batch fails they are written one at a time, so a bad page only fails
+// itself. Returns the error of every page that couldnt be written, in the order the pages were given
+pub fn write_pages(pages: &mut [BasicIndexedPage], changed: &[bool], write: &mut dyn FnMut(&mut IndexBatch) -> Result<(), database::Error>) -> Vec<Option<database::Error>> {
+    let mut batch = IndexBatch::new();
+    for (page, _) in pages.iter_mut().zip(changed.iter()).filter(|(_, t)| **t) {
+        page.add_to_batch(&mut batch);
+    }
+    let error = match write(&mut batch) {
+        Ok(_) => return pages.iter().map(|_| None).collect(),
+        Err(t) => t
+    };
+
+    // the failed transaction was rolled back with its deltas, and every page moves the shared statistics once in its own
+    warn!("Couldnt write a batch of {} page(s), writing them one at a time {:?}", pages.len(), error);
+    return pages.iter_mut().zip(changed.iter()).map(|(page, changed)| {
+        if !changed {
+            return None;
+        }
+        let mut batch = IndexBatch::new();
+        page.add_to_batch(&mut batch);
+        return write(&mut batch).err();
+    }).collect();
 }
 
 pub trait InsertOrSum<K,V> { fn insert_or_sum(&mut self, key: K, val: V); }
@@ -89,4 +218,147 @@ where
             None => {}
         };
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawled_page::{CrawledPage, Word};
+    use analysis::{AnalysisConfig, AnalyzerConfig};
+    use analysis::filter::Stopwords;
+
+    fn analyzers() -> Analyzers {
+        let conf = AnalysisConfig {
+            stopwords_dir: String::new(),
+            fallback_language: "en".to_string(),
+            analyzers: HashMap::from([("standard".to_string(), AnalyzerConfig {
+                tokenizer: "whitespace".to_string(),
+                filters: vec!["normalize".to_string()]
+            })]),
+            fields: HashMap::from([("default".to_string(), "standard".to_string())]),
+            languages: HashMap::new()
+        };
+        return Analyzers::with_stopwords(&conf, Stopwords::empty()).unwrap();
+    }
+
+    fn crawled_page(doc_id: i64, words: &[(&str, &str, &str, i32)]) -> CrawledPage {
+        return CrawledPage {
+            doc_id: doc_id,
+            claimed_at: 0,
+            url: format!("https://example.com/{}", doc_id),
+            description: String::new(),
+            title: String::from("Title"),
+            language: String::from("en"),
+            words: words.iter().map(|(word, surface, parent, count)| Word {
+                word: word.to_string(),
+                surface: surface.to_string(),
+                parent: parent.to_string(),
+                count: *count,
+                positions: (0..*count as u32).collect()
+            }).collect()
+        };
+    }
+
+    fn page(words: &[(&str, u64)]) -> BasicIndexedPage {
+        let mut page = BasicIndexedPage::new();
+        page.title = String::from("Title");
+        for (word, weight) in words {
+            page.words.insert(word.to_string(), *weight);
+            page.positions.insert(word.to_string(), vec![*weight as u32]);
+        }
+        return page;
+    }
+
+    #[test]
+    fn fingerprint_only_changes_with_the_content() {
+        let a = page(&[("search", 3), ("engine", 5), ("rust", 1)]);
+        let b = page(&[("rust", 1), ("engine", 5), ("search", 3)]);
+        let c = page(&[("rust", 2), ("engine", 5), ("search", 3)]);
+
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), c.fingerprint());
+    }
+
+    #[test]
+    fn from_crawled_page_weighs_and_prunes() {
+        let analyzers = analyzers();
+        let mut weights = WeightConfig {
+            fields: HashMap::from([("title".to_string(), 5), ("default".to_string(), 2)]),
+            min_weight: 3,
+            max_terms_per_page: 0
+        };
+        let words = [
+            ("rust", "Rust", "title", 1),
+            ("rust", "rust", "p", 2),
+            ("engine", "engine", "p", 2),
+            // h1 isnt listed, so it gets the default
+            ("search", "search", "h1", 2),
+            ("fast", "fast", "p", 1),
+        ];
+
+        let mut page = BasicIndexedPage::new();
+        page.from_crawled_page(crawled_page(1, &words), &analyzers, &weights);
+        assert_eq!(page.words, HashMap::from([("rust".to_string(), 9), ("engine".to_string(), 4), ("search".to_string(), 4)]));
+        // below min_weight
+        assert!(!page.surface_forms.contains_key("fast"));
+        assert_eq!(BasicIndexedPage::surface_form(&page.surface_forms, "rust"), "Rust");
+
+        // engine and search weigh the same, the tie goes to the smaller term
+        weights.max_terms_per_page = 2;
+        let mut page = BasicIndexedPage::new();
+        page.from_crawled_page(crawled_page(1, &words), &analyzers, &weights);
+        assert_eq!(page.words, HashMap::from([("rust".to_string(), 9), ("engine".to_string(), 4)]));
+        assert!(!page.surface_forms.contains_key("search"));
+
+        // without a default every unlisted field counts once
+        weights.fields.remove("default");
+        weights.max_terms_per_page = 0;
+        weights.min_weight = 0;
+        let mut page = BasicIndexedPage::new();
+        page.from_crawled_page(crawled_page(1, &words), &analyzers, &weights);
+        assert_eq!(page.words["rust"], 7);
+        assert_eq!(page.words["fast"], 1);
+    }
+
+    #[test]
+    fn index_pages_writes_in_order_and_falls_back() {
+        let analyzers = analyzers();
+        let weights = WeightConfig { fields: HashMap::new(), min_weight: 0, max_terms_per_page: 0 };
+        let crawled: Vec<CrawledPage> = (1..=5).map(|t| crawled_page(t, &[("rust", "rust", "p", t as i32), ("engine", "engine", "h1", 1)])).collect();
+
+        // more pages than workers, and more workers than pages
+        let mut indexed = index_pages(&crawled, &analyzers, &weights, 2);
+        assert_eq!(indexed.iter().map(|t| t.doc_id).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(index_pages(&crawled[..2], &analyzers, &weights, 8).len(), 2);
+        assert_eq!(indexed[2].words["rust"], 3);
+
+        let changed = vec![true, false, true, true, true];
+        let mut batches: Vec<Vec<i64>> = vec![];
+        let errors = write_pages(&mut indexed, &changed, &mut |batch| {
+            batches.push(batch.doc_ids.clone());
+            // the rows of a batch come in the order of its pages
+            let page_ids: Vec<i64> = batch.pages.iter().map(|t| t.0).collect();
+            let mut word_ids: Vec<i64> = batch.words.iter().map(|t| t.0).collect();
+            word_ids.dedup();
+            assert_eq!(page_ids, batch.doc_ids);
+            assert_eq!(word_ids, batch.doc_ids);
+            assert_eq!(batch.statistics.iter().map(|t| t.0).collect::<Vec<_>>(), batch.doc_ids);
+
+            if batch.doc_ids.contains(&3) {
+                return Err(database::Error::SQLError(None, String::from("broken page")));
+            }
+            return Ok(());
+        });
+
+        // the unchanged page isnt written, and only the bad page fails once the batch is split up
+        assert_eq!(batches, vec![vec![1, 3, 4, 5], vec![1], vec![3], vec![4], vec![5]]);
+        assert_eq!(errors.iter().map(|t| t.is_some()).collect::<Vec<_>>(), vec![false, false, true, false, false]);
+
+        let mut batches = 0;
+        let errors = write_pages(&mut indexed, &changed, &mut |_| {
+            batches += 1;
+            return Ok(());
+        });
+        assert_eq!(batches, 1);
+        assert!(errors.iter().all(|t| t.is_none()));
+    }
+}
@@ -1,10 +1,20 @@
-use log::{error, info, debug, LevelFilter};
+use log::{error, info, warn, debug, LevelFilter};
 use env_logger::Builder;
+use std::collections::HashMap;
+
+use indexed_page::IndexedPage;
 
-mod dictionary;
 mod crawled_page;
 mod indexed_page;
+mod batch;
+mod statistics;
 mod database;
+mod segment;
+mod segment_index;
+mod segment_server;
+mod failed_index;
+mod consistency;
+mod reindex;
 mod config;
 
 fn main() {
@@ -18,28 +28,180 @@ fn main() {
         .init();
 
     let db: &mut dyn database::Database = &mut database::PostgresDatabase::new(&conf.database);
-    let dict: &dyn dictionary::Dictionary = &dictionary::BasicDictionary::new();
 
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|t| t.as_str()) {
+        Some("failed") => {
+            match failed_index::run_command(&args[2..], db) {
+                Ok(_) => {},
+                Err(t) => eprintln!("{}", t)
+            };
+            return;
+        },
+        Some("check") => {
+            match consistency::run_command(&args[2..], db, &conf.indexer.weights) {
+                Ok(_) => {},
+                Err(t) => eprintln!("{}", t)
+            };
+            return;
+        },
+        Some("reindex") => {
+            let analyzers = analysis::Analyzers::new(&conf.analysis).expect("Couldnt build the analyzers from the analysis config");
+            match reindex::run_command(&args[2..], db, &analyzers, conf.indexer.batch_size) {
+                Ok(_) => {},
+                Err(t) => eprintln!("{}", t)
+            };
+            return;
+        },
+        Some(t) => {
+            eprintln!("Unknown command '{}'", t);
+            return;
+        },
+        None => {}
+    };
+    let analyzers = analysis::Analyzers::new(&conf.analysis).expect("Couldnt build the analyzers from the analysis config");
+
+    let segments = match conf.indexer.segments.enabled {
+        true => {
+            let segments = segment_index::SegmentIndex::open(&conf.indexer.segments).expect("Couldnt open the index segments");
+            segments.spawn_merger(conf.indexer.segments.merge_factor, conf.indexer.segments.merge_interval);
+            let address = segment_server::spawn(segments.clone(), &conf.indexer.segments.listen).expect("Couldnt serve the index segments");
+            info!("Serving the index segments on {}", address);
+            Some(segments)
+        }
+        false => None
+    };
+
+    // the crawler notifies when it commits a page, time_between_indexes is only a fallback for missed notifications
     loop {
-        index(db, dict);
-        std::thread::sleep(std::time::Duration::from_secs(conf.indexer.time_between_indexes));
+        index(db, &analyzers, &conf.indexer, segments.as_ref());
+        match db.wait_for_pages(std::time::Duration::from_secs(conf.indexer.time_between_indexes)) {
+            true => {},
+            false => debug!("No pages crawled in {}s, checking anyway", conf.indexer.time_between_indexes)
+        };
     }
 }
 
-fn index(db: &mut dyn database::Database, dict: &dyn dictionary::Dictionary) {
+fn index(db: &mut dyn database::Database, analyzers: &analysis::Analyzers, conf: &config::IndexerConfig, segments: Option<&segment_index::SegmentIndex>) {
     info!("Index Starting");
-    for _ in 0..db.crawled_page_len() {
-        debug!("{} page(s) to crawl", db.crawled_page_len());
+    let workers = match conf.workers {
+        0 => std::thread::available_parallelism().map(|t| t.get()).unwrap_or(1),
+        t => t
+    };
+
+    let mut indexed_pages = 0;
+    loop {
+        let crawled = db.claim_crawled_pages(conf.claim_timeout, conf.batch_size as i64);
+        if crawled.len() == 0 {
+            break;
+        }
+        debug!("Indexing {} page(s)", crawled.len());
+
+        let mut indexed = indexed_page::index_pages(&crawled, analyzers, &conf.weights, workers);
+
+        // a page that would write exactly what is already indexed is only acked
+        let doc_ids: Vec<i64> = indexed.iter().map(|t| t.doc_id).collect();
+        let fingerprints = match db.indexed_fingerprints(&doc_ids) {
+            Ok(t) => t,
+            Err(t) => {
+                warn!("Couldnt read fingerprints, writing every page {:?}", t);
+                HashMap::new()
+            }
+        };
+        // the segments dont know about pages indexed before they were turned on, so those are written even if unchanged
+        let changed: Vec<bool> = indexed.iter()
+            .map(|t| fingerprints.get(&t.doc_id) != Some(&t.fingerprint()) || segments.is_some_and(|s| !s.contains(&t.url)))
+            .collect();
+        debug!("{} page(s) unchanged", changed.iter().filter(|t| !**t).count());
+
+        // pages only leave crawleddata once their index rows are committed. A page that couldnt be written goes to
+        // failed_index, where it is claimed again after its backoff
+        // with segments on the words only go there, and writing none to indexedwords drops what it still had of the pages
+        let errors = indexed_page::write_pages(&mut indexed, &changed, &mut |batch| {
+            if segments.is_some() {
+                batch.words.clear();
+            }
+            return batch.consume_into_db(db);
+        });
+        let mut written: Vec<bool> = vec![];
+        for (crawled, error) in crawled.iter().zip(errors) {
+            match error {
+                None => written.push(true),
+                Some(t) => {
+                    fail_page(db, crawled, &t.to_string(), &conf.retry);
+                    written.push(false);
+                }
+            };
+        }
 
-        let crawled = db.get_crawled_page().unwrap();
-        debug!("Indexing {}", crawled.url);
+        // with segments on they are the only copy of the words, so a page is acked once they are on disk. A page they
+        // couldnt take is failed like a database error, and since the segments dont have it the retry writes it again
+        match segments {
+            Some(t) => {
+                for (((page, crawled), written), _) in indexed.iter().zip(crawled.iter()).zip(written.iter_mut()).zip(changed.iter()).filter(|(_, changed)| **changed) {
+                    if !*written {
+                        continue;
+                    }
+                    match page.write_into_segments(t) {
+                        Ok(_) => {},
+                        Err(t) => {
+                            fail_page(db, crawled, &format!("Couldnt write to the segments {:?}", t), &conf.retry);
+                            *written = false;
+                        }
+                    };
+                }
+                match t.flush() {
+                    Ok(_) => {},
+                    Err(t) => for (crawled, written) in crawled.iter().zip(written.iter_mut()).filter(|(_, written)| **written) {
+                        fail_page(db, crawled, &format!("Couldnt flush the segments {:?}", t), &conf.retry);
+                        *written = false;
+                    }
+                };
+            },
+            None => {}
+        }
 
-        let indexed: &mut dyn indexed_page::IndexedPage = &mut indexed_page::BasicIndexedPage::new();
-        indexed.from_crawled_page(crawled, dict);
-        match indexed.consume_into_db(db) {
-            Ok(_) => {},
-            Err(t) => error!("{:?}", t),
+        let acked = written.iter().filter(|t| **t).count();
+        match db.ack_crawled_pages(&mut crawled.iter().zip(written.iter()).filter(|(_, t)| **t).map(|(page, _)| (page.doc_id, page.claimed_at))) {
+            Ok(_) => indexed_pages += acked,
+            Err(t) => error!("Couldnt ack {} page(s) {:?}", acked, t),
         };
     }
-    info!("Index Complete");
-}
\ No newline at end of file
+
+    // pages the crawler found gone for good
+    let mut removed_pages = 0;
+    loop {
+        let removed = match db.remove_tombstoned_pages(conf.batch_size as i64) {
+            Ok(t) => t,
+            Err(t) => {
+                error!("Couldnt remove tombstoned pages {:?}", t);
+                break;
+            }
+        };
+        if removed.len() == 0 {
+            break;
+        }
+        removed_pages += removed.len();
+
+        match segments {
+            Some(t) => for (_, url) in removed.iter() {
+                match t.remove(url) {
+                    Ok(_) => {},
+                    Err(t) => error!("Couldnt remove {} from the segments {:?}", url, t),
+                };
+            },
+            None => {}
+        }
+    }
+
+    info!("Index Complete, {} page(s) indexed, {} removed", indexed_pages, removed_pages);
+}
+
+// the page goes to failed_index and is claimed again after its backoff
+fn fail_page(db: &mut dyn database::Database, crawled: &crawled_page::CrawledPage, error: &str, retry: &config::RetryConfig) {
+    error!("Couldnt index {} {}", crawled.url, error);
+    match db.fail_crawled_page(crawled, error, retry) {
+        Ok(_) => {},
+        Err(t) => error!("Couldnt record the failure of {} {:?}", crawled.url, t),
+    };
+}
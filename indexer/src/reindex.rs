@@ -0,0 +1,132 @@
+use analysis::{dates, page_text};
+
+use crate::crawled_page::{CrawledPage, Word};
+use crate::database;
+
+// the text the crawler kept of a page, as it was on the last crawl
+pub struct StoredPage {
+    pub doc_id: i64,
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub language: String,
+    // compressed with analysis::page_text
+    pub text: Vec<u8>
+}
+
+// which stored pages to reindex, everything when all of them are None
+pub struct ReindexFilter {
+    // the host of the url, or any subdomain of it
+    pub domain: Option<String>,
+    // unix seconds the page was crawled at or after
+    pub since: Option<i64>,
+    // unix seconds the page was crawled before
+    pub until: Option<i64>
+}
+
+// indexer reindex [--domain example.com] [--since 2025-01-31] [--until 2025-02-28]
+//
+// runs the stored text of every page through the analyzers again and hands the words back to the indexer the same way the
+// crawler does, so the running indexer rewrites them with the current config. Nothing is taken out of the index first, so
+// searches keep working and a page only changes once its new rows are committed
+pub fn run_command(args: &[String], db: &mut dyn database::Database, analyzers: &analysis::Analyzers, batch_size: usize) -> Result<(), String> {
+    let filter = match parse_filter(args) {
+        Ok(t) => t,
+        Err(t) => return Err(format!("{}\nusage: indexer reindex [--domain example.com] [--since yyyy-mm-dd] [--until yyyy-mm-dd]", t))
+    };
+
+    let mut after = i64::MIN;
+    let mut queued = 0;
+    loop {
+        let stored = match db.stored_pages(after, &filter, batch_size as i64) {
+            Ok(t) => t,
+            Err(t) => return Err(format!("{:?}", t))
+        };
+        if stored.len() == 0 {
+            break;
+        }
+        after = stored.last().unwrap().doc_id;
+
+        let mut pages = vec![];
+        for page in stored {
+            match analyze(page, analyzers) {
+                Ok(t) => pages.push(t),
+                Err((url, t)) => eprintln!("Skipping {} {}", url, t)
+            };
+        }
+
+        match db.queue_for_reindex(&pages) {
+            Ok(t) => queued += t,
+            Err(t) => return Err(format!("{:?}", t))
+        };
+    }
+
+    // a page the crawler already has waiting for the indexer isnt queued again, it gets the new config anyway
+    println!("{} page(s) queued for reindexing", queued);
+    return Ok(());
+}
+
+fn analyze(page: StoredPage, analyzers: &analysis::Analyzers) -> Result<CrawledPage, (String, String)> {
+    let runs = match page_text::decompress(&page.text) {
+        Ok(t) => t,
+        Err(t) => return Err((page.url, t))
+    };
+
+    let words = page_text::analyze(&runs, &page.language, analyzers).into_iter()
+        .map(|t| Word {
+            word: t.term,
+            surface: t.surface,
+            parent: t.parent,
+            count: t.count,
+            positions: t.positions
+        }).collect();
+
+    return Ok(CrawledPage {
+        doc_id: page.doc_id,
+        claimed_at: 0,
+        url: page.url,
+        description: page.description,
+        title: page.title,
+        language: page.language,
+        words: words
+    });
+}
+
+fn parse_filter(args: &[String]) -> Result<ReindexFilter, String> {
+    let mut filter = ReindexFilter { domain: None, since: None, until: None };
+
+    let mut args = args.iter();
+    loop {
+        let flag = match args.next() {
+            Some(t) => t,
+            None => return Ok(filter)
+        };
+        let value = match args.next() {
+            Some(t) => t,
+            None => return Err(format!("{} needs a value", flag))
+        };
+        match flag.as_str() {
+            "--domain" => filter.domain = Some(value.to_lowercase().trim_start_matches("www.").to_string()),
+            "--since" => filter.since = Some(dates::parse_date(value)?),
+            "--until" => filter.until = Some(dates::parse_date(value)?),
+            _ => return Err(format!("Unknown option '{}'", flag))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reindex_filter() {
+        let args: Vec<String> = ["--domain", "www.Example.com", "--since", "2024-12-31"].iter().map(|t| t.to_string()).collect();
+        let filter = parse_filter(&args).unwrap();
+        assert_eq!(filter.domain.as_deref(), Some("example.com"));
+        assert_eq!(filter.since, Some(1735603200));
+        assert_eq!(filter.until, None);
+
+        assert!(parse_filter(&args[..1]).is_err());
+        assert!(parse_filter(&["--until".to_string(), "yesterday".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+// An immutable piece of the inverted index on disk. A segment is four files sharing an id:
+//   <id>.terms     fst map from each term to where its postings start in <id>.postings
+//   <id>.postings  per term the number of docs, then per doc the gap from the previous doc id, the weight and the encoded positions
+//   <id>.docs      the doc store, what each doc id stands for
+//   <id>.del       tombstones, one bit per doc. This is the only file that changes once a segment is written
+// Doc ids are local to a segment and count up from 0. Numbers are LEB128 varints, like the position lists
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use analysis::positions::{read_varint, write_varint};
+use fst::{Map, MapBuilder, Streamer};
+use memmap2::Mmap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredDocument {
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub language: String
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Posting {
+    pub doc: u32,
+    pub weight: u64,
+    // encoded with analysis::positions
+    pub positions: Vec<u8>
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum SegmentError {
+    IOError(std::io::Error),
+    FSTError(fst::Error),
+    Corrupt(String)
+}
+
+impl From<std::io::Error> for SegmentError {
+    fn from(error: std::io::Error) -> Self {
+        return SegmentError::IOError(error);
+    }
+}
+
+impl From<fst::Error> for SegmentError {
+    fn from(error: fst::Error) -> Self {
+        return SegmentError::FSTError(error);
+    }
+}
+
+// collects documents in memory until there are enough to be worth a segment
+pub struct SegmentBuilder {
+    documents: Vec<StoredDocument>,
+    postings: BTreeMap<String, Vec<Posting>>,
+    deleted: Vec<bool>,
+    urls: HashMap<String, u32>
+}
+
+impl SegmentBuilder {
+    pub fn new() -> Self {
+        return SegmentBuilder {
+            documents: vec![],
+            postings: BTreeMap::new(),
+            deleted: vec![],
+            urls: HashMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.documents.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.documents.is_empty();
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        return self.urls.contains_key(url);
+    }
+
+    pub fn documents(&self) -> impl Iterator<Item = (u32, &StoredDocument)> {
+        return self.documents.iter().enumerate().filter(|(doc, _)| !self.deleted[*doc]).map(|(doc, document)| (doc as u32, document));
+    }
+
+    // terms are (term, weight, encoded positions). A url thats already in the builder is replaced
+    pub fn add(&mut self, document: StoredDocument, terms: &mut dyn Iterator<Item = (String, u64, Vec<u8>)>) -> u32 {
+        self.remove(&document.url);
+
+        let doc = self.push_document(document);
+        for (term, weight, positions) in terms {
+            self.push_posting(term, Posting {
+                doc: doc,
+                weight: weight,
+                positions: positions
+            });
+        }
+        return doc;
+    }
+
+    pub fn remove(&mut self, url: &str) -> bool {
+        return match self.urls.remove(url) {
+            Some(t) => {
+                self.deleted[t as usize] = true;
+                true
+            }
+            None => false
+        };
+    }
+
+    fn push_document(&mut self, document: StoredDocument) -> u32 {
+        let doc = self.documents.len() as u32;
+        self.urls.insert(document.url.clone(), doc);
+        self.documents.push(document);
+        self.deleted.push(false);
+        return doc;
+    }
+
+    // postings of a term have to be pushed in doc id order
+    fn push_posting(&mut self, term: String, posting: Posting) {
+        self.postings.entry(term).or_default().push(posting);
+    }
+
+    pub fn write(&self, dir: &Path, id: u64) -> Result<(), SegmentError> {
+        let mut postings: Vec<u8> = vec![];
+        let mut terms = MapBuilder::new(BufWriter::new(File::create(segment_path(dir, id, "terms"))?))?;
+
+        for (term, list) in self.postings.iter() {
+            terms.insert(term, postings.len() as u64)?;
+
+            write_varint(&mut postings, list.len() as u64);
+            let mut last = 0;
+            for posting in list {
+                write_varint(&mut postings, (posting.doc - last) as u64);
+                write_varint(&mut postings, posting.weight);
+                write_varint(&mut postings, posting.positions.len() as u64);
+                postings.extend_from_slice(&posting.positions);
+                last = posting.doc;
+            }
+        }
+        terms.finish()?;
+        fs::write(segment_path(dir, id, "postings"), &postings)?;
+
+        // a count, then where each doc starts, then the docs themselves
+        let mut records: Vec<u8> = vec![];
+        let mut docs: Vec<u8> = vec![];
+        docs.extend_from_slice(&(self.documents.len() as u32).to_le_bytes());
+        for document in self.documents.iter() {
+            docs.extend_from_slice(&(records.len() as u64).to_le_bytes());
+            for field in [&document.url, &document.title, &document.description, &document.language] {
+                write_varint(&mut records, field.len() as u64);
+                records.extend_from_slice(field.as_bytes());
+            }
+        }
+        docs.extend_from_slice(&records);
+        fs::write(segment_path(dir, id, "docs"), &docs)?;
+
+        return write_tombstones(dir, id, &self.deleted);
+    }
+}
+
+pub struct SegmentReader {
+    pub id: u64,
+    dir: PathBuf,
+    terms: Map<Mmap>,
+    postings: Mmap,
+    docs: Mmap,
+    count: u32,
+    deleted: Mutex<Vec<bool>>
+}
+
+impl SegmentReader {
+    pub fn open(dir: &Path, id: u64) -> Result<Self, SegmentError> {
+        let docs = map_file(&segment_path(dir, id, "docs"))?;
+        let count = match docs.get(0..4) {
+            Some(t) => u32::from_le_bytes([t[0], t[1], t[2], t[3]]),
+            None => return Err(SegmentError::Corrupt(format!("{} has no doc count", id)))
+        };
+
+        let tombstones = fs::read(segment_path(dir, id, "del"))?;
+        let deleted = (0..count as usize).map(|doc| tombstones.get(doc / 8).map(|t| t & (1 << (doc % 8)) != 0).unwrap_or(false)).collect();
+
+        return Ok(SegmentReader {
+            id: id,
+            dir: dir.to_path_buf(),
+            terms: Map::new(map_file(&segment_path(dir, id, "terms"))?)?,
+            postings: map_file(&segment_path(dir, id, "postings"))?,
+            docs: docs,
+            count: count,
+            deleted: Mutex::new(deleted)
+        });
+    }
+
+    pub fn count(&self) -> u32 {
+        return self.count;
+    }
+
+    // doc ids come out of the postings file, so one past the doc count means a corrupt segment rather than a bug
+    pub fn is_deleted(&self, doc: u32) -> Result<bool, SegmentError> {
+        return match self.deleted.lock().unwrap().get(doc as usize) {
+            Some(t) => Ok(*t),
+            None => Err(SegmentError::Corrupt(format!("{} has no doc {}", self.id, doc)))
+        };
+    }
+
+    pub fn delete(&self, doc: u32) -> Result<(), SegmentError> {
+        let mut deleted = self.deleted.lock().unwrap();
+        match deleted.get_mut(doc as usize) {
+            Some(t) => *t = true,
+            None => return Err(SegmentError::Corrupt(format!("{} has no doc {}", self.id, doc)))
+        };
+        return write_tombstones(&self.dir, self.id, &deleted);
+    }
+
+    // postings of the docs that arent deleted
+    pub fn postings(&self, term: &str) -> Result<Vec<Posting>, SegmentError> {
+        let offset = match self.terms.get(term) {
+            Some(t) => t,
+            None => return Ok(vec![])
+        };
+
+        let mut postings = vec![];
+        for posting in self.read_postings(offset)? {
+            if !self.is_deleted(posting.doc)? {
+                postings.push(posting);
+            }
+        }
+        return Ok(postings);
+    }
+
+    fn read_postings(&self, offset: u64) -> Result<Vec<Posting>, SegmentError> {
+        let mut reader = VarintReader { bytes: &self.postings, offset: offset as usize, segment: self.id };
+
+        let count = reader.next()?;
+        let mut postings = Vec::with_capacity(count as usize);
+        let mut last = 0;
+        for _ in 0..count {
+            let doc = last + reader.next()? as u32;
+            let weight = reader.next()?;
+            let length = reader.next()? as usize;
+            postings.push(Posting {
+                doc: doc,
+                weight: weight,
+                positions: reader.take(length)?.to_vec()
+            });
+            last = doc;
+        }
+        return Ok(postings);
+    }
+
+    pub fn document(&self, doc: u32) -> Result<StoredDocument, SegmentError> {
+        let header = 4 + self.count as usize * 8;
+        let offset = match self.docs.get(4 + doc as usize * 8..4 + doc as usize * 8 + 8) {
+            Some(t) => u64::from_le_bytes(t.try_into().unwrap()) as usize,
+            None => return Err(SegmentError::Corrupt(format!("{} has no doc {}", self.id, doc)))
+        };
+
+        let mut reader = VarintReader { bytes: &self.docs, offset: header + offset, segment: self.id };
+        let mut fields: Vec<String> = vec![];
+        for _ in 0..4 {
+            let length = reader.next()? as usize;
+            fields.push(String::from_utf8_lossy(reader.take(length)?).into_owned());
+        }
+
+        let language = fields.pop().unwrap();
+        let description = fields.pop().unwrap();
+        let title = fields.pop().unwrap();
+        let url = fields.pop().unwrap();
+        return Ok(StoredDocument { url, title, description, language });
+    }
+
+    pub fn live_documents(&self) -> Result<Vec<(u32, StoredDocument)>, SegmentError> {
+        let mut documents = vec![];
+        for doc in 0..self.count {
+            if !self.is_deleted(doc)? {
+                documents.push((doc, self.document(doc)?));
+            }
+        }
+        return Ok(documents);
+    }
+}
+
+struct VarintReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    segment: u64
+}
+
+impl<'a> VarintReader<'a> {
+    fn next(&mut self) -> Result<u64, SegmentError> {
+        return match self.bytes.get(self.offset..).and_then(read_varint) {
+            Some((value, length)) => {
+                self.offset += length;
+                Ok(value)
+            }
+            None => Err(SegmentError::Corrupt(format!("{} ends in the middle of a number", self.segment)))
+        };
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SegmentError> {
+        return match self.bytes.get(self.offset..self.offset + length) {
+            Some(t) => {
+                self.offset += length;
+                Ok(t)
+            }
+            None => Err(SegmentError::Corrupt(format!("{} ends in the middle of a record", self.segment)))
+        };
+    }
+}
+
+// copies the live docs of several segments into one builder. Returns where each old doc went, None for the deleted ones,
+// so deletes that happen while the merged segment is written can be carried over
+pub fn merge(segments: &[Arc<SegmentReader>]) -> Result<(SegmentBuilder, Vec<Vec<Option<u32>>>), SegmentError> {
+    let mut builder = SegmentBuilder::new();
+    let mut doc_maps = vec![];
+
+    // segments are copied one after another, so doc ids keep going up inside every posting list
+    for segment in segments {
+        let mut doc_map: Vec<Option<u32>> = vec![None; segment.count() as usize];
+        for (doc, document) in segment.live_documents()? {
+            doc_map[doc as usize] = Some(builder.push_document(document));
+        }
+
+        let mut stream = segment.terms.stream();
+        while let Some((term, offset)) = stream.next() {
+            let term = String::from_utf8_lossy(term).into_owned();
+            for posting in segment.read_postings(offset)? {
+                match doc_map.get(posting.doc as usize) {
+                    Some(Some(t)) => builder.push_posting(term.clone(), Posting { doc: *t, ..posting }),
+                    Some(None) => {},
+                    None => return Err(SegmentError::Corrupt(format!("{} has no doc {}", segment.id, posting.doc)))
+                }
+            }
+        }
+        doc_maps.push(doc_map);
+    }
+
+    return Ok((builder, doc_maps));
+}
+
+pub fn remove_files(dir: &Path, id: u64) {
+    for extension in ["terms", "postings", "docs", "del"] {
+        let _ = fs::remove_file(segment_path(dir, id, extension));
+    }
+}
+
+fn segment_path(dir: &Path, id: u64, extension: &str) -> PathBuf {
+    return dir.join(format!("{:08}.{}", id, extension));
+}
+
+fn map_file(path: &Path) -> Result<Mmap, SegmentError> {
+    let file = File::open(path)?;
+    // segment files are never written to once they exist, tombstones are replaced rather than changed in place
+    return Ok(unsafe { Mmap::map(&file)? });
+}
+
+// written next to the old file and renamed over it, so a crash leaves either the old tombstones or the new ones
+fn write_tombstones(dir: &Path, id: u64, deleted: &[bool]) -> Result<(), SegmentError> {
+    let mut bits = vec![0u8; deleted.len().div_ceil(8)];
+    for (doc, _) in deleted.iter().enumerate().filter(|(_, deleted)| **deleted) {
+        bits[doc / 8] |= 1 << (doc % 8);
+    }
+
+    let temporary = segment_path(dir, id, "del.tmp");
+    fs::write(&temporary, &bits)?;
+    fs::rename(&temporary, segment_path(dir, id, "del"))?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_rejects_unknown_docs() {
+        let dir = std::env::temp_dir().join(format!("indexer-segment-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut builder = SegmentBuilder::new();
+        let document = StoredDocument { url: "a".to_string(), title: String::new(), description: String::new(), language: "en".to_string() };
+        builder.add(document, &mut vec![("run".to_string(), 3, vec![])].into_iter());
+        // a posting for a doc the segment doesnt have, like a postings file that went bad would hold
+        builder.push_posting("walk".to_string(), Posting { doc: 7, weight: 1, positions: vec![] });
+        builder.write(&dir, 0).unwrap();
+
+        let reader = Arc::new(SegmentReader::open(&dir, 0).unwrap());
+        assert_eq!(reader.is_deleted(0).unwrap(), false);
+        assert!(matches!(reader.is_deleted(1), Err(SegmentError::Corrupt(_))));
+        assert!(matches!(reader.delete(1), Err(SegmentError::Corrupt(_))));
+        assert_eq!(reader.postings("run").unwrap().len(), 1);
+        assert!(matches!(reader.postings("walk"), Err(SegmentError::Corrupt(_))));
+        assert!(matches!(merge(&[reader]), Err(SegmentError::Corrupt(_))));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
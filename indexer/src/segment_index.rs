@@ -0,0 +1,266 @@
+// Keeps the set of live segments. New pages collect in a builder and are written out as a segment when flushed or once
+// there are flush_documents of them, a page indexed again tombstones its old copy, and a background thread merges segments
+// once there are merge_factor of them so lookups dont have to visit too many. The list of live segments is the
+// "segments" file in the directory, replaced in one rename so a crash never leaves it half written
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use log::{info, warn};
+
+use crate::config::SegmentConfig;
+use crate::segment::{self, Posting, SegmentBuilder, SegmentError, SegmentReader, StoredDocument};
+
+#[derive(Clone)]
+pub struct SegmentIndex {
+    dir: PathBuf,
+    flush_documents: usize,
+    state: Arc<Mutex<SegmentState>>
+}
+
+struct SegmentState {
+    segments: Vec<Arc<SegmentReader>>,
+    next_id: u64,
+    buffer: SegmentBuilder,
+    // the segment and doc every url that made it to disk lives in
+    locations: HashMap<String, (u64, u32)>
+}
+
+impl SegmentIndex {
+    pub fn open(conf: &SegmentConfig) -> Result<Self, SegmentError> {
+        let dir = PathBuf::from(&conf.dir);
+        fs::create_dir_all(&dir)?;
+
+        let ids: Vec<u64> = match fs::read_to_string(dir.join("segments")) {
+            Ok(t) => t.lines().filter_map(|line| line.trim().parse().ok()).collect(),
+            Err(t) if t.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(t) => return Err(SegmentError::IOError(t))
+        };
+
+        let mut state = SegmentState {
+            segments: vec![],
+            next_id: ids.iter().max().map(|t| t + 1).unwrap_or(0),
+            buffer: SegmentBuilder::new(),
+            locations: HashMap::new()
+        };
+
+        for id in ids {
+            let reader = SegmentReader::open(&dir, id)?;
+            for (doc, document) in reader.live_documents()? {
+                state.locations.insert(document.url, (id, doc));
+            }
+            state.segments.push(Arc::new(reader));
+        }
+
+        info!("Opened {} segment(s) with {} page(s) from {}", state.segments.len(), state.locations.len(), dir.display());
+
+        return Ok(SegmentIndex {
+            dir: dir,
+            flush_documents: conf.flush_documents,
+            state: Arc::new(Mutex::new(state))
+        });
+    }
+
+    // terms are (term, weight, encoded positions)
+    pub fn add(&self, document: StoredDocument, terms: &mut dyn Iterator<Item = (String, u64, Vec<u8>)>) -> Result<(), SegmentError> {
+        let mut state = self.state.lock().unwrap();
+
+        Self::delete_from_segments(&mut state, &document.url)?;
+        state.buffer.add(document, terms);
+
+        if state.buffer.len() >= self.flush_documents {
+            return self.flush_locked(&mut state);
+        }
+        return Ok(());
+    }
+
+    pub fn remove(&self, url: &str) -> Result<bool, SegmentError> {
+        let mut state = self.state.lock().unwrap();
+
+        let in_buffer = state.buffer.remove(url);
+        let in_segments = Self::delete_from_segments(&mut state, url)?;
+        return Ok(in_buffer || in_segments);
+    }
+
+    // pages in the buffer count, they are on their way to disk
+    pub fn contains(&self, url: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        return state.locations.contains_key(url) || state.buffer.contains(url);
+    }
+
+    pub fn flush(&self) -> Result<(), SegmentError> {
+        let mut state = self.state.lock().unwrap();
+        return self.flush_locked(&mut state);
+    }
+
+    // (url, posting) for every live page with the term, pages still in the buffer arent searchable until they are flushed
+    pub fn postings(&self, term: &str) -> Result<Vec<(String, Posting)>, SegmentError> {
+        let segments = self.state.lock().unwrap().segments.clone();
+
+        let mut postings = vec![];
+        for segment in segments {
+            for posting in segment.postings(term)? {
+                postings.push((segment.document(posting.doc)?.url, posting));
+            }
+        }
+        return Ok(postings);
+    }
+
+    pub fn spawn_merger(&self, merge_factor: usize, merge_interval: u64) {
+        let index = self.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(std::time::Duration::from_secs(merge_interval));
+                match index.merge(merge_factor) {
+                    Ok(_) => {},
+                    Err(t) => warn!("Couldnt merge segments {:?}", t)
+                }
+            }
+        });
+    }
+
+    // merges every segment into one once there are merge_factor of them. The merged segment is written without holding
+    // the lock, so indexing carries on, and deletes that happened in the meantime are copied over before it goes live
+    pub fn merge(&self, merge_factor: usize) -> Result<bool, SegmentError> {
+        let (inputs, id) = {
+            let mut state = self.state.lock().unwrap();
+            if state.segments.len() < merge_factor.max(2) {
+                return Ok(false);
+            }
+            state.next_id += 1;
+            (state.segments.clone(), state.next_id - 1)
+        };
+
+        let (builder, doc_maps) = segment::merge(&inputs)?;
+        builder.write(&self.dir, id)?;
+        let merged = SegmentReader::open(&self.dir, id)?;
+
+        let mut state = self.state.lock().unwrap();
+        for (input, doc_map) in inputs.iter().zip(doc_maps.iter()) {
+            for (old, new) in doc_map.iter().enumerate() {
+                match new {
+                    Some(t) if input.is_deleted(old as u32)? => merged.delete(*t)?,
+                    _ => {}
+                }
+            }
+        }
+
+        for (doc, document) in merged.live_documents()? {
+            state.locations.insert(document.url, (id, doc));
+        }
+
+        let input_ids: HashSet<u64> = inputs.iter().map(|t| t.id).collect();
+        state.segments.retain(|t| !input_ids.contains(&t.id));
+        state.segments.insert(0, Arc::new(merged));
+        Self::write_segment_list(&self.dir, &state.segments)?;
+        drop(state);
+
+        for id in input_ids.iter() {
+            segment::remove_files(&self.dir, *id);
+        }
+        info!("Merged {} segment(s) into {}", input_ids.len(), id);
+
+        return Ok(true);
+    }
+
+    fn flush_locked(&self, state: &mut SegmentState) -> Result<(), SegmentError> {
+        if state.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.buffer.write(&self.dir, id)?;
+
+        let reader = SegmentReader::open(&self.dir, id)?;
+        for (doc, document) in state.buffer.documents() {
+            state.locations.insert(document.url.clone(), (id, doc));
+        }
+        state.segments.push(Arc::new(reader));
+        state.buffer = SegmentBuilder::new();
+
+        return Self::write_segment_list(&self.dir, &state.segments);
+    }
+
+    fn delete_from_segments(state: &mut SegmentState, url: &str) -> Result<bool, SegmentError> {
+        let (id, doc) = match state.locations.remove(url) {
+            Some(t) => t,
+            None => return Ok(false)
+        };
+
+        match state.segments.iter().find(|t| t.id == id) {
+            Some(t) => t.delete(doc)?,
+            None => {}
+        }
+        return Ok(true);
+    }
+
+    fn write_segment_list(dir: &Path, segments: &[Arc<SegmentReader>]) -> Result<(), SegmentError> {
+        let list: String = segments.iter().map(|t| format!("{}\n", t.id)).collect();
+        fs::write(dir.join("segments.tmp"), list)?;
+        fs::rename(dir.join("segments.tmp"), dir.join("segments"))?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(url: &str) -> StoredDocument {
+        return StoredDocument {
+            url: url.to_string(),
+            title: format!("{} title", url),
+            description: String::new(),
+            language: "en".to_string()
+        };
+    }
+
+    fn terms(terms: &[(&str, u64)]) -> Vec<(String, u64, Vec<u8>)> {
+        return terms.iter().map(|(term, weight)| (term.to_string(), *weight, analysis::positions::encode(&[1, 4]))).collect();
+    }
+
+    #[test]
+    fn segments_flush_replace_and_merge() {
+        let dir = std::env::temp_dir().join(format!("indexer-segments-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let conf = SegmentConfig {
+            enabled: true,
+            dir: dir.to_string_lossy().into_owned(),
+            listen: String::new(),
+            flush_documents: 2,
+            merge_factor: 2,
+            merge_interval: 60
+        };
+
+        let index = SegmentIndex::open(&conf).unwrap();
+        index.add(document("a"), &mut terms(&[("run", 3), ("fast", 1)]).into_iter()).unwrap();
+        index.add(document("b"), &mut terms(&[("run", 5)]).into_iter()).unwrap();
+        // b again, tombstoning the copy in the first segment
+        index.add(document("b"), &mut terms(&[("walk", 2)]).into_iter()).unwrap();
+        index.add(document("c"), &mut terms(&[("run", 1)]).into_iter()).unwrap();
+
+        let mut run: Vec<(String, u64)> = index.postings("run").unwrap().into_iter().map(|(url, posting)| (url, posting.weight)).collect();
+        run.sort();
+        assert_eq!(run, vec![("a".to_string(), 3), ("c".to_string(), 1)]);
+
+        assert_eq!(index.merge(conf.merge_factor).unwrap(), true);
+        assert_eq!(index.state.lock().unwrap().segments.len(), 1);
+
+        // a fresh open only sees the merged segment, with b's old copy gone
+        let reopened = SegmentIndex::open(&conf).unwrap();
+        let walk = reopened.postings("walk").unwrap();
+        assert_eq!(walk.len(), 1);
+        assert_eq!(walk[0].0, "b");
+        assert_eq!(analysis::positions::decode(&walk[0].1.positions), vec![1, 4]);
+        assert_eq!(reopened.postings("run").unwrap().len(), 2);
+
+        assert_eq!(reopened.remove("a").unwrap(), true);
+        assert_eq!(reopened.postings("fast").unwrap().len(), 0);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,166 @@
+// Answers the term lookups of the backend from the segments, so with segments on the words of the index never go to postgres.
+// One route: POST /postings with {"terms": [...], "urls": [...], "limit": n} returns the postings of every term as
+// [{"url", "term", "weight", "positions"}], heaviest first. urls narrows it to those pages and limit 0 returns them all
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::thread;
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::segment::SegmentError;
+use crate::segment_index::SegmentIndex;
+
+#[derive(Deserialize)]
+pub struct PostingsRequest {
+    pub terms: Vec<String>,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub limit: usize
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PostingResponse {
+    pub url: String,
+    pub term: String,
+    pub weight: u64,
+    pub positions: Vec<u32>
+}
+
+// serves lookups on a thread of its own until the indexer exits. Returns the address it listens on
+pub fn spawn(index: SegmentIndex, listen: &str) -> Result<SocketAddr, String> {
+    let server = match Server::http(listen) {
+        Ok(t) => t,
+        Err(t) => return Err(t.to_string())
+    };
+    let address = match server.server_addr().to_ip() {
+        Some(t) => t,
+        None => return Err(format!("{} isnt an ip address", listen))
+    };
+
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            if *request.method() != Method::Post || request.url() != "/postings" {
+                let _ = request.respond(Response::from_string("Not found").with_status_code(404));
+                continue;
+            }
+
+            let lookup: PostingsRequest = match serde_json::from_reader(request.as_reader()) {
+                Ok(t) => t,
+                Err(t) => {
+                    let _ = request.respond(Response::from_string(format!("Bad request {}", t)).with_status_code(400));
+                    continue;
+                }
+            };
+
+            let response = match postings(&index, &lookup) {
+                Ok(t) => Response::from_string(serde_json::to_string(&t).unwrap_or_default())
+                    .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap()),
+                Err(t) => {
+                    error!("Couldnt look up {:?} in the segments {:?}", lookup.terms, t);
+                    Response::from_string("Couldnt read the segments").with_status_code(500)
+                }
+            };
+            debug!("Looked up {:?}", lookup.terms);
+            let _ = request.respond(response);
+        }
+    });
+
+    return Ok(address);
+}
+
+pub fn postings(index: &SegmentIndex, request: &PostingsRequest) -> Result<Vec<PostingResponse>, SegmentError> {
+    let urls: HashSet<&str> = request.urls.iter().map(|t| t.as_str()).collect();
+
+    let mut postings = vec![];
+    for term in request.terms.iter() {
+        for (url, posting) in index.postings(term)? {
+            if !urls.is_empty() && !urls.contains(url.as_str()) {
+                continue;
+            }
+            postings.push(PostingResponse {
+                url: url,
+                term: term.clone(),
+                weight: posting.weight,
+                positions: analysis::positions::decode(&posting.positions)
+            });
+        }
+    }
+
+    // the same order the indexedwords query had, with the url breaking ties so pages dont swap between requests
+    postings.sort_by(|a, b| b.weight.cmp(&a.weight).then_with(|| a.url.cmp(&b.url)).then_with(|| a.term.cmp(&b.term)));
+    if request.limit > 0 {
+        postings.truncate(request.limit);
+    }
+    return Ok(postings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use crate::config::SegmentConfig;
+    use crate::segment::StoredDocument;
+
+    fn add(index: &SegmentIndex, url: &str, terms: &[(&str, u64, &[u32])]) {
+        let document = StoredDocument { url: url.to_string(), title: String::new(), description: String::new(), language: "en".to_string() };
+        index.add(document, &mut terms.iter().map(|(term, weight, positions)| (term.to_string(), *weight, analysis::positions::encode(positions)))).unwrap();
+    }
+
+    #[test]
+    fn segment_server_postings() {
+        let dir = std::env::temp_dir().join(format!("indexer-segment-server-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let conf = SegmentConfig {
+            enabled: true,
+            dir: dir.to_string_lossy().into_owned(),
+            listen: "127.0.0.1:0".to_string(),
+            flush_documents: 100,
+            merge_factor: 2,
+            merge_interval: 60
+        };
+        let index = SegmentIndex::open(&conf).unwrap();
+        add(&index, "a", &[("run", 3, &[0, 5]), ("runner", 4, &[2])]);
+        add(&index, "b", &[("run", 5, &[1])]);
+        add(&index, "c", &[("walk", 1, &[0])]);
+        index.flush().unwrap();
+
+        let request = PostingsRequest { terms: vec!["run".to_string(), "runner".to_string()], urls: vec![], limit: 0 };
+        let weights: Vec<(String, String, u64)> = postings(&index, &request).unwrap().into_iter().map(|t| (t.url, t.term, t.weight)).collect();
+        assert_eq!(weights, vec![
+            ("b".to_string(), "run".to_string(), 5),
+            ("a".to_string(), "runner".to_string(), 4),
+            ("a".to_string(), "run".to_string(), 3)
+        ]);
+
+        let request = PostingsRequest { terms: vec!["run".to_string()], urls: vec!["a".to_string()], limit: 0 };
+        assert_eq!(postings(&index, &request).unwrap(), vec![PostingResponse { url: "a".to_string(), term: "run".to_string(), weight: 3, positions: vec![0, 5] }]);
+
+        let request = PostingsRequest { terms: vec!["run".to_string(), "runner".to_string()], urls: vec![], limit: 1 };
+        assert_eq!(postings(&index, &request).unwrap().len(), 1);
+
+        // and the same over http
+        let address = spawn(index.clone(), &conf.listen).unwrap();
+        let body = r#"{"terms": ["walk"]}"#;
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "POST /postings HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(r#"[{"url":"c","term":"walk","weight":1,"positions":[0]}]"#));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "POST /postings HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1\r\nConnection: close\r\n\r\n{{").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
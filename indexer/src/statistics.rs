@@ -0,0 +1,119 @@
+// What BM25F needs to rank a page instead of summing weights: how long the page and each of its fields are, and how often
+// each term appears in each field. The global side (document frequencies, document counts and total lengths per field)
+// is kept up to date by the database as pages are written, replaced or removed
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// the field name the whole document is counted under, no html tag can be called this
+pub const DOCUMENT_FIELD: &str = "*";
+
+// field -> (documents, total length) to add to its global totals
+pub type FieldDeltas = BTreeMap<String, (i64, i64)>;
+// term -> documents to add to its document frequency
+pub type TermDeltas = BTreeMap<String, i64>;
+
+// what a batch changes about the global statistics. Pages of a batch share most of these rows, so the deltas are summed
+// over the batch and written once before it commits, in key order so every indexer locks the rows in the same order
+pub struct StatisticsDeltas {
+    pub fields: FieldDeltas,
+    pub terms: TermDeltas
+}
+
+impl StatisticsDeltas {
+    pub fn new() -> Self {
+        return StatisticsDeltas {
+            fields: FieldDeltas::new(),
+            terms: TermDeltas::new()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PageStatistics {
+    // field -> number of terms in it, with the whole document under DOCUMENT_FIELD
+    pub field_lengths: HashMap<String, u64>,
+    // (term, field) -> times the term appears in the field
+    pub term_frequencies: HashMap<(String, String), u64>
+}
+
+impl PageStatistics {
+    pub fn new() -> Self {
+        return PageStatistics {
+            field_lengths: HashMap::new(),
+            term_frequencies: HashMap::new()
+        }
+    }
+
+    pub fn add(self: &mut Self, term: &str, field: &str, count: u64) {
+        *self.field_lengths.entry(DOCUMENT_FIELD.to_string()).or_default() += count;
+        *self.field_lengths.entry(field.to_string()).or_default() += count;
+        *self.term_frequencies.entry((term.to_string(), field.to_string())).or_default() += count;
+    }
+
+    // pruned terms still count towards the lengths, only their frequencies go
+    pub fn retain_terms(self: &mut Self, terms: &HashMap<String, u64>) {
+        self.term_frequencies.retain(|(term, _), _| terms.contains_key(term));
+    }
+
+    // takes out what the page had before, its distinct terms and its fields as (field, length), and puts in what it has now
+    pub fn add_deltas(self: &Self, old_terms: &[String], old_fields: &[(String, i64)], deltas: &mut StatisticsDeltas) {
+        for term in old_terms {
+            *deltas.terms.entry(term.clone()).or_default() -= 1;
+        }
+        let new_terms: BTreeSet<&String> = self.term_frequencies.keys().map(|(term, _)| term).collect();
+        for term in new_terms {
+            *deltas.terms.entry(term.clone()).or_default() += 1;
+        }
+
+        for (field, length) in old_fields {
+            let delta = deltas.fields.entry(field.clone()).or_default();
+            delta.0 -= 1;
+            delta.1 -= length;
+        }
+        for (field, length) in self.field_lengths.iter() {
+            let delta = deltas.fields.entry(field.clone()).or_default();
+            delta.0 += 1;
+            delta.1 += *length as i64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_statistics() {
+        let mut statistics = PageStatistics::new();
+        statistics.add("run", "p", 3);
+        statistics.add("run", "h1", 1);
+        statistics.add("fast", "p", 2);
+
+        assert_eq!(statistics.field_lengths[DOCUMENT_FIELD], 6);
+        assert_eq!(statistics.field_lengths["p"], 5);
+        assert_eq!(statistics.term_frequencies[&("run".to_string(), "p".to_string())], 3);
+
+        statistics.retain_terms(&HashMap::from([("run".to_string(), 31)]));
+        assert_eq!(statistics.term_frequencies.len(), 2);
+        assert_eq!(statistics.field_lengths[DOCUMENT_FIELD], 6);
+
+        // the same page indexed again with a longer p and no h1 any more, and a new page with only a p
+        let mut deltas = StatisticsDeltas::new();
+        statistics.add_deltas(&["run".to_string(), "walk".to_string()], &[(DOCUMENT_FIELD.to_string(), 4), ("p".to_string(), 2), ("h1".to_string(), 2)], &mut deltas);
+        let mut other = PageStatistics::new();
+        other.add("fast", "p", 4);
+        other.add_deltas(&[], &[], &mut deltas);
+
+        assert_eq!(deltas.fields.into_iter().collect::<Vec<_>>(), vec![
+            (DOCUMENT_FIELD.to_string(), (1, 6)),
+            ("h1".to_string(), (0, -1)),
+            ("p".to_string(), (1, 7)),
+        ]);
+        // run stays, walk is gone from the page and the other page brings fast too
+        assert_eq!(deltas.terms.into_iter().collect::<Vec<_>>(), vec![
+            ("fast".to_string(), 1),
+            ("run".to_string(), 0),
+            ("walk".to_string(), -1),
+        ]);
+    }
+}